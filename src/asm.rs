@@ -0,0 +1,324 @@
+//! A minimal assembler for CHIP-8 programs.
+//!
+//! Sources are line based, using the common Cowgod mnemonics:
+//!
+//! ```text
+//! ; comments run to the end of the line
+//! start:  LD V0, 0x05     ; labels end with a colon
+//!         LD F, V0
+//!         DRW V1, V2, 5
+//! loop:   JP loop
+//! data:   DB 0xF0, 0x90, 0xF0
+//! ```
+//!
+//! XO-CHIP's long `LD I, LONG` takes its 16-bit address from a `DW` on the next line, and
+//! `AUDIO` and `PITCH Vx` set up its sound.
+//!
+//! Numbers may be decimal, `0x`/`#` hexadecimal or `0b` binary, and anywhere an
+//! address is expected a label may be used instead.
+
+use alloc::{collections::BTreeMap, format, string::String, vec::Vec};
+use core::fmt;
+
+use crate::{Opcode, PROGRAM_START_ADDRESS};
+
+/// An error in the source, with the 1-based line it was found on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for AsmError {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Operand<'a> {
+    V(usize),
+    I,
+    IndirectI,
+    DelayTimer,
+    SoundTimer,
+    Key,
+    Font,
+    Bcd,
+    Rpl,
+    Long,
+    Number(u16),
+    Label(&'a str),
+}
+
+struct Statement<'a> {
+    line: usize,
+    mnemonic: String,
+    operands: Vec<Operand<'a>>,
+}
+
+/// Assembles `source` into a ROM to be loaded at `PROGRAM_START_ADDRESS`.
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    // First pass: find where every label ends up
+    let mut labels = BTreeMap::new();
+    let mut statements = Vec::new();
+    let mut address = PROGRAM_START_ADDRESS;
+    for (i, line) in source.lines().enumerate() {
+        let line_number = i + 1;
+        let mut code = line.split(';').next().unwrap_or("").trim();
+        if let Some((label, rest)) = code.split_once(':') {
+            let label = label.trim();
+            if !is_identifier(label) {
+                return Err(error(line_number, format!("invalid label '{}'", label)));
+            }
+
+            if labels.insert(label, address).is_some() {
+                return Err(error(line_number, format!("duplicate label '{}'", label)));
+            }
+
+            code = rest.trim();
+        }
+
+        if code.is_empty() {
+            continue;
+        }
+
+        let (mnemonic, rest) = code.split_once(char::is_whitespace).unwrap_or((code, ""));
+        let operands = rest
+            .split(',')
+            .map(str::trim)
+            .filter(|operand| !operand.is_empty())
+            .map(|operand| parse_operand(operand).ok_or(operand))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|operand| error(line_number, format!("invalid operand '{}'", operand)))?;
+
+        let statement = Statement {
+            line: line_number,
+            mnemonic: mnemonic.to_ascii_uppercase(),
+            operands,
+        };
+
+        address += match statement.mnemonic.as_str() {
+            "DB" => statement.operands.len(),
+            "DW" => statement.operands.len() * 2,
+            _ => 2,
+        };
+        statements.push(statement);
+    }
+
+    // Second pass: encode with every label known
+    let mut rom = Vec::new();
+    for statement in statements {
+        let resolve = |operand: &Operand, max: u16| -> Result<u16, AsmError> {
+            let value = match *operand {
+                Operand::Number(value) => value,
+                Operand::Label(label) => match labels.get(label) {
+                    Some(&address) => address as u16,
+                    None => {
+                        return Err(error(
+                            statement.line,
+                            format!("undefined label '{}'", label),
+                        ))
+                    }
+                },
+                _ => return Err(error(statement.line, "expected a number or label".into())),
+            };
+
+            if value > max {
+                return Err(error(
+                    statement.line,
+                    format!("{:#x} doesn't fit in {:#x}", value, max),
+                ));
+            }
+
+            Ok(value)
+        };
+
+        match statement.mnemonic.as_str() {
+            "DB" => {
+                for operand in &statement.operands {
+                    rom.push(resolve(operand, 0xFF)? as u8);
+                }
+                continue;
+            }
+            "DW" => {
+                for operand in &statement.operands {
+                    rom.extend_from_slice(&resolve(operand, 0xFFFF)?.to_be_bytes());
+                }
+                continue;
+            }
+            _ => {}
+        }
+
+        let opcode = encode(&statement, &resolve)?;
+        rom.extend_from_slice(&opcode.encode().to_be_bytes());
+    }
+
+    Ok(rom)
+}
+
+fn encode(
+    statement: &Statement,
+    resolve: &dyn Fn(&Operand, u16) -> Result<u16, AsmError>,
+) -> Result<Opcode, AsmError> {
+    use Operand::*;
+
+    let address = |operand| resolve(operand, 0xFFF);
+    let byte = |operand| resolve(operand, 0xFF).map(|value| value as u8);
+    let operands = statement.operands.as_slice();
+    let opcode = match (statement.mnemonic.as_str(), operands) {
+        ("CLS", []) => Opcode::ClearScreen,
+        ("RET", []) => Opcode::Return,
+        ("SYS", [nnn]) => Opcode::MachineCall(address(nnn)?),
+        ("JP", [V(0), nnn]) => Opcode::JumpOffset(0, address(nnn)?),
+        ("JP", [nnn]) => Opcode::Jump(address(nnn)?),
+        ("CALL", [nnn]) => Opcode::Call(address(nnn)?),
+        ("SE", [V(x), V(y)]) => Opcode::SkipEqual(*x, *y),
+        ("SE", [V(x), nn]) => Opcode::SkipEqualByte(*x, byte(nn)?),
+        ("SNE", [V(x), V(y)]) => Opcode::SkipNotEqual(*x, *y),
+        ("SNE", [V(x), nn]) => Opcode::SkipNotEqualByte(*x, byte(nn)?),
+        ("LD", [V(x), V(y)]) => Opcode::Move(*x, *y),
+        ("LD", [V(x), DelayTimer]) => Opcode::LoadDelay(*x),
+        ("LD", [V(x), Key]) => Opcode::WaitKey(*x),
+        ("LD", [V(x), IndirectI]) => Opcode::LoadRegisters(*x),
+        ("LD", [V(x), Rpl]) => Opcode::LoadFlags(*x),
+        ("LD", [V(x), nn]) => Opcode::LoadByte(*x, byte(nn)?),
+        ("LD", [I, Long]) => Opcode::LoadLongIndex,
+        ("LD", [I, nnn]) => Opcode::LoadIndex(address(nnn)?),
+        ("LD", [DelayTimer, V(x)]) => Opcode::SetDelay(*x),
+        ("LD", [SoundTimer, V(x)]) => Opcode::SetSound(*x),
+        ("LD", [Font, V(x)]) => Opcode::LoadFont(*x),
+        ("LD", [Bcd, V(x)]) => Opcode::StoreBcd(*x),
+        ("LD", [IndirectI, V(x)]) => Opcode::StoreRegisters(*x),
+        ("LD", [Rpl, V(x)]) => Opcode::StoreFlags(*x),
+        ("ADD", [V(x), V(y)]) => Opcode::Add(*x, *y),
+        ("ADD", [I, V(x)]) => Opcode::AddIndex(*x),
+        ("ADD", [V(x), nn]) => Opcode::AddByte(*x, byte(nn)?),
+        ("OR", [V(x), V(y)]) => Opcode::Or(*x, *y),
+        ("AND", [V(x), V(y)]) => Opcode::And(*x, *y),
+        ("XOR", [V(x), V(y)]) => Opcode::Xor(*x, *y),
+        ("SUB", [V(x), V(y)]) => Opcode::Sub(*x, *y),
+        // Vy = Vx, so the one operand form shifts Vx whether or not the shift_vy quirk is on
+        ("SHR", [V(x)]) => Opcode::ShiftRight(*x, *x),
+        ("SHR", [V(x), V(y)]) => Opcode::ShiftRight(*x, *y),
+        ("SUBN", [V(x), V(y)]) => Opcode::SubReverse(*x, *y),
+        ("SHL", [V(x)]) => Opcode::ShiftLeft(*x, *x),
+        ("SHL", [V(x), V(y)]) => Opcode::ShiftLeft(*x, *y),
+        ("RND", [V(x), nn]) => Opcode::Random(*x, byte(nn)?),
+        ("DRW", [V(x), V(y), n]) => Opcode::Draw(*x, *y, resolve(n, 0xF)? as u8),
+        ("AUDIO", []) => Opcode::LoadAudio,
+        ("PITCH", [V(x)]) => Opcode::SetPitch(*x),
+        ("SKP", [V(x)]) => Opcode::SkipKeyPressed(*x),
+        ("SKNP", [V(x)]) => Opcode::SkipKeyNotPressed(*x),
+        _ => {
+            return Err(error(
+                statement.line,
+                format!("unknown instruction '{}'", statement.mnemonic),
+            ))
+        }
+    };
+
+    Ok(opcode)
+}
+
+fn parse_operand(operand: &str) -> Option<Operand<'_>> {
+    let upper = operand.to_ascii_uppercase();
+    let parsed = match upper.as_str() {
+        "I" => Operand::I,
+        "[I]" => Operand::IndirectI,
+        "DT" => Operand::DelayTimer,
+        "ST" => Operand::SoundTimer,
+        "K" => Operand::Key,
+        "F" => Operand::Font,
+        "B" => Operand::Bcd,
+        "R" => Operand::Rpl,
+        "LONG" => Operand::Long,
+        _ => {
+            if let Some(register) = upper.strip_prefix('V') {
+                if register.len() == 1 {
+                    if let Ok(x) = usize::from_str_radix(register, 16) {
+                        return Some(Operand::V(x));
+                    }
+                }
+            }
+
+            if let Some(number) = parse_number(&upper) {
+                Operand::Number(number)
+            } else if is_identifier(operand) {
+                Operand::Label(operand)
+            } else {
+                return None;
+            }
+        }
+    };
+
+    Some(parsed)
+}
+
+fn parse_number(value: &str) -> Option<u16> {
+    if let Some(hex) = value.strip_prefix("0X").or_else(|| value.strip_prefix('#')) {
+        u16::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = value.strip_prefix("0B") {
+        u16::from_str_radix(binary, 2).ok()
+    } else {
+        value.parse().ok()
+    }
+}
+
+fn is_identifier(value: &str) -> bool {
+    let mut chars = value.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn error(line: usize, message: String) -> AsmError {
+    AsmError { line, message }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assembles_instructions_labels_and_data() {
+        let rom = assemble(
+            "
+            ; draw a digit forever
+            start:  LD V0, 5
+                    LD F, V0
+                    DRW V1, V2, 5
+            loop:   JP loop
+                    LD I, sprite
+                    LD [I], V3
+                    SHR V4
+            sprite: DB 0xF0, #90, 0b11110000
+            ",
+        )
+        .unwrap();
+
+        assert_eq!(
+            rom,
+            [
+                0x60, 0x05, 0xF0, 0x29, 0xD1, 0x25, 0x12, 0x06, 0xA2, 0x0E, 0xF3, 0x55, 0x84, 0x46,
+                0xF0, 0x90, 0xF0
+            ]
+        );
+        assert_eq!(assemble("SHL VA").unwrap(), [0x8A, 0xAE]);
+    }
+
+    #[test]
+    fn reports_errors_with_line_numbers() {
+        assert_eq!(
+            assemble("CLS\nJP nowhere").unwrap_err(),
+            AsmError {
+                line: 2,
+                message: "undefined label 'nowhere'".into()
+            }
+        );
+        assert_eq!(assemble("LD V0, 256").unwrap_err().line, 1);
+        assert_eq!(assemble("FOO V0").unwrap_err().line, 1);
+    }
+}
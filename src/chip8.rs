@@ -1,17 +1,62 @@
-use std::io::Error;
+use alloc::{
+    boxed::Box,
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::time::Duration;
 
+pub use builder::Chip8Builder;
+pub use error::Chip8Error;
 use fontset::FONTSET;
+pub use opcode::{decode, Opcode};
+pub use quirks::{Platform, Quirks};
+use rand::{rngs::StdRng, Rng, SeedableRng};
 
+mod builder;
+mod error;
 mod fontset;
+mod opcode;
+mod quirks;
 
+pub const MEMORY_SIZE: usize = 4096;
+/// XO-CHIP addresses the full 16 bits.
+pub const XO_CHIP_MEMORY_SIZE: usize = 0x10000;
 pub const FONTSET_START_ADDRESS: usize = 0x50;
 pub const PROGRAM_START_ADDRESS: usize = 0x200;
+/// How deep calls can nest on the original interpreters.
+pub const DEFAULT_STACK_DEPTH: usize = 16;
+/// XO-CHIP's initial pitch, playing the audio pattern at 4000 bits per second.
+pub const DEFAULT_PITCH: u8 = 64;
 
-#[derive(clap::ValueEnum, Clone, Default, Debug)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[cfg_attr(feature = "wasm", wasm_bindgen::prelude::wasm_bindgen)]
+#[derive(Clone, Default, Debug, serde::Serialize)]
 pub enum Mode {
     #[default]
     Chip8,
     Chip48,
+    XoChip,
+}
+
+impl Mode {
+    /// The following mode, wrapping around after the last.
+    pub fn next(&self) -> Self {
+        match self {
+            Mode::Chip8 => Mode::Chip48,
+            Mode::Chip48 => Mode::XoChip,
+            Mode::XoChip => Mode::Chip8,
+        }
+    }
+
+    /// Bytes of memory the mode can address.
+    pub fn memory_size(&self) -> usize {
+        match self {
+            Mode::Chip8 | Mode::Chip48 => MEMORY_SIZE,
+            Mode::XoChip => XO_CHIP_MEMORY_SIZE,
+        }
+    }
 }
 
 pub enum Actions {
@@ -19,6 +64,7 @@ pub enum Actions {
     Redraw,
 }
 
+#[derive(Clone, Debug)]
 pub struct KeyboardState {
     pub keys_pressed: [bool; 16],
     pub pressed_key: Option<u8>,
@@ -33,32 +79,161 @@ impl KeyboardState {
     }
 }
 
+impl Default for KeyboardState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Called with the display whenever it changes.
+pub type DrawCallback = Box<dyn FnMut(&[[u8; 64]; 32])>;
+
+/// Called with `true` when the beeper starts and `false` when it stops.
+pub type SoundCallback = Box<dyn FnMut(bool)>;
+
+/// Called for `0NNN` with the machine and NNN, returning whether it stood in for the machine
+/// code there. Calls it doesn't handle are ignored, or errors in strict mode.
+pub type MachineCallHandler = Box<dyn FnMut(&mut Chip8, u16) -> bool>;
+
+/// The registered callbacks. Closures can't be cloned, so clones start without any.
+#[derive(Default)]
+struct Callbacks {
+    on_draw: Option<DrawCallback>,
+    on_sound: Option<SoundCallback>,
+    on_machine_call: Option<MachineCallHandler>,
+}
+
+impl Clone for Callbacks {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+/// A snapshot of the machine for other tools, with the display packed as `display_bits`.
+#[derive(serde::Serialize)]
+pub struct MachineState {
+    pub registers: [u8; 16],
+    pub index_register: u16,
+    pub program_counter: usize,
+    pub stack_pointer: usize,
+    pub stack: Vec<usize>,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    pub mode: Mode,
+    pub cycles: u64,
+    pub display: [u64; 32],
+}
+
+/// Cloning makes a save state: everything, including the random number generator's
+/// position, is copied except the callbacks.
+#[derive(Clone)]
 pub struct Chip8 {
-    pub memory: [u8; 4096],
+    /// `Mode::memory_size` bytes.
+    pub memory: Vec<u8>,
     pub registers: [u8; 16],
     pub index_register: u16,
     pub program_counter: usize,
-    pub stack: [usize; 16],
-    pub stack_pointer: i8,
+    /// Return addresses, innermost last.
+    pub stack: Vec<usize>,
+    /// Calls nested deeper than this overflow the stack.
+    pub max_stack_depth: usize,
     pub delay_timer: u8,
     pub sound_timer: u8,
     pub display: [[u8; 64]; 32],
+    /// SCHIP's persistent user flags, from the HP-48's RPL registers.
+    pub rpl_flags: [u8; 8],
     pub mode: Mode,
+    pub quirks: Quirks,
+    /// Report suspicious programs as errors instead of carrying on.
+    pub strict: bool,
+    /// Make writes below `PROGRAM_START_ADDRESS`, where the font lives, errors.
+    pub protect_interpreter: bool,
+    load_address: usize,
+    program_size: usize,
+    needs_redraw: bool,
+    drawn_this_frame: bool,
+    waiting_for_vblank: bool,
+    /// The key `FX0A` saw pressed and is waiting to be released.
+    latched_key: Option<u8>,
+    waiting_for_key: bool,
+    /// XO-CHIP's 1-bit sample, played from the high bit of the first byte onwards.
+    audio_pattern: [u8; 16],
+    pitch: u8,
+    /// Set once, the first time an opcode looks like it's meant for another mode.
+    mode_warned: bool,
+    mode_warning: Option<String>,
+    /// The last opcode skipped for not being in the mode's instruction set, and its address.
+    unknown_opcode: Option<(u16, usize)>,
+    /// Instructions executed per opcode group, when profiling.
+    profile: Option<[u64; 16]>,
+    /// Which of `Opcode::TEMPLATES` have run, a bit each, when tracking coverage.
+    coverage: Option<u64>,
+    /// Instructions stepped since the machine was created or reset, plus the extra each draw
+    /// costs under the `draw_cost` quirk.
+    cycles: u64,
+    rng: StdRng,
+    callbacks: Callbacks,
+}
+
+impl Default for Chip8 {
+    fn default() -> Self {
+        Self::new(Mode::default())
+    }
+}
+
+impl TryFrom<&[u8]> for Chip8 {
+    type Error = Chip8Error;
+
+    /// A machine in the default mode with `program` loaded.
+    fn try_from(program: &[u8]) -> Result<Self, Self::Error> {
+        Self::from_rom(program, Mode::default())
+    }
+}
+
+/// Seeded from the OS when available, otherwise from a fixed seed; use
+/// `Chip8::set_seed` to vary it without `std`.
+fn new_rng() -> StdRng {
+    #[cfg(feature = "std")]
+    return StdRng::from_entropy();
+
+    #[cfg(not(feature = "std"))]
+    return StdRng::seed_from_u64(0);
 }
 
 impl Chip8 {
     pub fn new(mode: Mode) -> Self {
         let mut machine = Self {
-            memory: [0; 4096],
+            memory: vec![0; mode.memory_size()],
             registers: [0; 16],
             index_register: 0,
             program_counter: PROGRAM_START_ADDRESS,
-            stack: [0; 16],
-            stack_pointer: -1,
+            stack: Vec::with_capacity(DEFAULT_STACK_DEPTH),
+            max_stack_depth: DEFAULT_STACK_DEPTH,
             delay_timer: 0,
             sound_timer: 0,
             display: [[0; 64]; 32],
+            rpl_flags: [0; 8],
+            quirks: Quirks::from(&mode),
             mode,
+            strict: false,
+            protect_interpreter: false,
+            load_address: PROGRAM_START_ADDRESS,
+            program_size: 0,
+            needs_redraw: false,
+            drawn_this_frame: false,
+            waiting_for_vblank: false,
+            latched_key: None,
+            waiting_for_key: false,
+            audio_pattern: [0; 16],
+            pitch: DEFAULT_PITCH,
+            mode_warned: false,
+            mode_warning: None,
+            unknown_opcode: None,
+            profile: None,
+            coverage: None,
+            cycles: 0,
+            rng: new_rng(),
+            callbacks: Callbacks::default(),
         };
 
         FONTSET.iter().enumerate().for_each(|(i, &byte)| {
@@ -68,10 +243,385 @@ impl Chip8 {
         machine
     }
 
-    pub fn load(&mut self, program: &[u8]) {
-        program.iter().enumerate().for_each(|(i, &byte)| {
-            self.memory[PROGRAM_START_ADDRESS + i] = byte;
-        });
+    /// Configures a machine step by step, for more than `new` or `from_rom` set up.
+    pub fn builder() -> Chip8Builder {
+        Chip8Builder::default()
+    }
+
+    /// A machine in `mode` with `program` loaded, ready to run.
+    pub fn from_rom(program: &[u8], mode: Mode) -> Result<Self, Chip8Error> {
+        Self::builder().mode(mode).load(program).build()
+    }
+
+    /// Replace the built-in font with `font`, 5 bytes per glyph and at most 16 glyphs.
+    pub fn load_font(&mut self, font: &[u8]) -> Result<(), Chip8Error> {
+        if !font.len().is_multiple_of(5) || font.len() > FONTSET.len() {
+            return Err(Chip8Error::InvalidFont { size: font.len() });
+        }
+
+        self.memory[FONTSET_START_ADDRESS..FONTSET_START_ADDRESS + font.len()]
+            .copy_from_slice(font);
+        Ok(())
+    }
+
+    /// Switch modes, resetting the quirks to that mode's defaults.
+    pub fn set_mode(&mut self, mode: Mode) {
+        // Leaving XO-CHIP shrinks memory, so addresses above the new top wrap into it
+        let size = mode.memory_size();
+        self.memory.resize(size, 0);
+        self.program_counter %= size;
+        self.index_register = (self.index_register as usize % size) as u16;
+        for address in self.stack.iter_mut() {
+            *address %= size;
+        }
+
+        self.quirks = Quirks::from(&mode);
+        self.mode = mode;
+        log::debug!("switched to {:?} with {:?}", self.mode, self.quirks);
+    }
+
+    /// Mark the start of a new frame, letting a draw stalled by the display wait quirk go ahead.
+    pub fn vblank(&mut self) {
+        self.drawn_this_frame = false;
+        self.waiting_for_vblank = false;
+    }
+
+    /// Whether a draw is stalled until the next `vblank`, so the rest of the frame can be skipped.
+    pub fn is_waiting_for_vblank(&self) -> bool {
+        self.waiting_for_vblank
+    }
+
+    /// Whether `FX0A` is stalled waiting for a key, so the rest of the frame can be skipped.
+    pub fn is_waiting_for_key(&self) -> bool {
+        self.waiting_for_key
+    }
+
+    /// Returns the hint, once per machine, when the program uses an opcode from a mode it isn't
+    /// running in, e.g. an SCHIP ROM run as CHIP-8.
+    pub fn take_mode_warning(&mut self) -> Option<String> {
+        self.mode_warning.take()
+    }
+
+    /// Returns the last unknown opcode that was skipped and its address, if there was one since
+    /// the previous call.
+    pub fn take_unknown_opcode(&mut self) -> Option<(u16, usize)> {
+        self.unknown_opcode.take()
+    }
+
+    fn check_mode(&mut self, opcode: &Opcode) {
+        if self.mode_warned {
+            return;
+        }
+
+        let hint = match *opcode {
+            Opcode::StoreFlags(_) | Opcode::LoadFlags(_) if matches!(self.mode, Mode::Chip8) => {
+                "a CHIP-48 opcode, try the chip48 mode"
+            }
+            _ if opcode.is_xo_chip() && !matches!(self.mode, Mode::XoChip) => {
+                "an XO-CHIP opcode, try the xo-chip mode"
+            }
+            // Scrolling, hires and the big font
+            Opcode::MachineCall(0x0C0..=0x0CF | 0x0FB..=0x0FF) => {
+                "a SUPER-CHIP opcode, which isn't supported"
+            }
+            Opcode::Unknown(opcode) if opcode & 0xF0FF == 0xF030 => {
+                "a SUPER-CHIP opcode, which isn't supported"
+            }
+            _ => return,
+        };
+
+        let warning = format!(
+            "{:04X} at {:#06x} is {}",
+            opcode.encode(),
+            self.program_counter - 2,
+            hint
+        );
+        log::warn!("{}", warning);
+        self.mode_warned = true;
+        self.mode_warning = Some(warning);
+    }
+
+    fn check_write(&self, target: usize) -> Result<(), Chip8Error> {
+        if self.protect_interpreter && target < PROGRAM_START_ADDRESS {
+            return Err(Chip8Error::ProtectedWrite {
+                address: self.program_counter - 2,
+                target,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Reseed the random number generator used by `CXNN` so runs are reproducible.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    /// Start counting executed instructions by opcode group, the high nibble.
+    pub fn enable_profiling(&mut self) {
+        self.profile.get_or_insert([0; 16]);
+    }
+
+    /// Instructions executed per opcode group since profiling was enabled.
+    pub fn profile(&self) -> Option<&[u64; 16]> {
+        self.profile.as_ref()
+    }
+
+    /// Start tracking which kinds of instruction run.
+    pub fn enable_coverage(&mut self) {
+        self.coverage.get_or_insert(0);
+    }
+
+    /// The `Opcode::TEMPLATES` executed since coverage was enabled, in template order.
+    pub fn coverage(&self) -> Option<Vec<&'static str>> {
+        self.coverage.map(|covered| {
+            Opcode::TEMPLATES
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| covered & 1 << i != 0)
+                .map(|(_, &template)| template)
+                .collect()
+        })
+    }
+
+    /// Register a callback for display changes, as an alternative to polling `Actions`.
+    pub fn set_on_draw(&mut self, callback: DrawCallback) {
+        self.callbacks.on_draw = Some(callback);
+    }
+
+    /// Register a callback for the beeper turning on or off.
+    pub fn set_on_sound(&mut self, callback: SoundCallback) {
+        self.callbacks.on_sound = Some(callback);
+    }
+
+    /// Register a host routine for `0NNN` machine code calls, to experiment with interpreters
+    /// that implemented some.
+    pub fn set_on_machine_call(&mut self, handler: MachineCallHandler) {
+        self.callbacks.on_machine_call = Some(handler);
+    }
+
+    /// Whether the display changed since the last `take_redraw`.
+    pub fn needs_redraw(&self) -> bool {
+        self.needs_redraw
+    }
+
+    /// Returns whether the display changed and clears the flag, so several draws
+    /// in one frame only cost one redraw.
+    pub fn take_redraw(&mut self) -> bool {
+        core::mem::take(&mut self.needs_redraw)
+    }
+
+    /// Loads a save state made by cloning, keeping this machine's callbacks. They hear about
+    /// the display and beeper changing to the snapshot's.
+    pub fn restore(&mut self, snapshot: Chip8) {
+        let was_beeping = self.is_beeping();
+        let callbacks = core::mem::take(&mut self.callbacks);
+        *self = snapshot;
+        self.callbacks = callbacks;
+        if was_beeping != self.is_beeping() {
+            if let Some(on_sound) = self.callbacks.on_sound.as_mut() {
+                on_sound(self.sound_timer > 0);
+            }
+        }
+
+        self.notify_draw();
+    }
+
+    /// The address `offset` bytes past I, wrapping at the end of the mode's memory like sprite
+    /// reads do.
+    fn index_address(&self, offset: usize) -> usize {
+        (self.index_register as usize + offset) % self.memory.len()
+    }
+
+    /// Moves past the next instruction, all four bytes of it for XO-CHIP's `F000 NNNN`.
+    fn skip(&mut self) {
+        let pc = self.program_counter;
+        let long = matches!(self.mode, Mode::XoChip)
+            && self.memory.get(pc..pc + 2) == Some(&[0xF0, 0x00][..]);
+        self.program_counter += if long { 4 } else { 2 };
+    }
+
+    fn notify_draw(&mut self) {
+        self.needs_redraw = true;
+        if let Some(on_draw) = self.callbacks.on_draw.as_mut() {
+            on_draw(&self.display);
+        }
+    }
+
+    fn set_sound_timer(&mut self, value: u8) {
+        let was_beeping = self.sound_timer > 0;
+        self.sound_timer = value;
+        if was_beeping != (value > 0) {
+            if let Some(on_sound) = self.callbacks.on_sound.as_mut() {
+                on_sound(value > 0);
+            }
+        }
+    }
+
+    /// Count the delay and sound timers down by one, to be called at 60hz.
+    ///
+    /// Returns whether the beeper should be on.
+    pub fn tick_timers(&mut self) -> bool {
+        self.delay_timer = self.delay_timer.saturating_sub(1);
+        self.set_sound_timer(self.sound_timer.saturating_sub(1));
+        self.is_beeping()
+    }
+
+    /// Sound plays for as long as the sound timer is non-zero.
+    pub fn is_beeping(&self) -> bool {
+        self.sound_timer > 0
+    }
+
+    /// The XO-CHIP sample to play while beeping, 128 bits from the high bit of the first byte.
+    pub fn audio_pattern(&self) -> &[u8; 16] {
+        &self.audio_pattern
+    }
+
+    /// The XO-CHIP pitch, which plays the pattern at `4000 * 2^((pitch - 64) / 48)` bits per
+    /// second.
+    pub fn pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    /// Where programs are loaded and start executing, `PROGRAM_START_ADDRESS` by default.
+    pub fn load_address(&self) -> usize {
+        self.load_address
+    }
+
+    /// Load and start programs at `address` instead, e.g. 0x600 for ETI-660 programs.
+    pub fn set_load_address(&mut self, address: usize) -> Result<(), Chip8Error> {
+        if address >= self.memory.len() {
+            return Err(Chip8Error::AddressOutOfRange { address });
+        }
+
+        self.load_address = address;
+        self.program_counter = address;
+        Ok(())
+    }
+
+    /// Clears the program and everything it changed, ready to `load` another. The mode, quirks,
+    /// font, load address and RPL flags are kept.
+    pub fn reset(&mut self) {
+        let font = FONTSET_START_ADDRESS..FONTSET_START_ADDRESS + FONTSET.len();
+        let mut memory = vec![0; self.memory.len()];
+        memory[font.clone()].copy_from_slice(&self.memory[font]);
+        self.memory = memory;
+        self.registers = [0; 16];
+        self.index_register = 0;
+        self.program_counter = self.load_address;
+        self.stack.clear();
+        self.delay_timer = 0;
+        self.sound_timer = 0;
+        self.display = [[0; 64]; 32];
+        self.program_size = 0;
+        self.drawn_this_frame = false;
+        self.waiting_for_vblank = false;
+        self.latched_key = None;
+        self.waiting_for_key = false;
+        self.audio_pattern = [0; 16];
+        self.pitch = DEFAULT_PITCH;
+        self.mode_warned = false;
+        self.mode_warning = None;
+        self.unknown_opcode = None;
+        self.cycles = 0;
+        self.notify_draw();
+    }
+
+    /// Load `program` at the load address.
+    pub fn load(&mut self, program: &[u8]) -> Result<(), Chip8Error> {
+        self.load_at(self.load_address, program)?;
+        self.program_size = program.len();
+        Ok(())
+    }
+
+    /// Copy `data` into memory at `address`, e.g. to place sprites for a test.
+    pub fn load_at(&mut self, address: usize, data: &[u8]) -> Result<(), Chip8Error> {
+        if address >= self.memory.len() {
+            return Err(Chip8Error::AddressOutOfRange { address });
+        }
+
+        let available = self.memory.len() - address;
+        if data.len() > available {
+            return Err(Chip8Error::ProgramTooLarge {
+                size: data.len(),
+                available,
+            });
+        }
+
+        self.memory[address..address + data.len()].copy_from_slice(data);
+        log::debug!("loaded {} bytes at {:#06x}", data.len(), address);
+        Ok(())
+    }
+
+    /// The registers, timers, mode and stack, one per line.
+    pub fn dump_state(&self) -> String {
+        let registers = |range: core::ops::Range<usize>| {
+            self.registers[range]
+                .iter()
+                .map(|value| format!("{:02x}", value))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        let stack = self
+            .stack
+            .iter()
+            .map(|address| format!("{:#06x}", address))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        [
+            format!("PC: {:#06x}", self.program_counter),
+            format!("I: {:#06x}", self.index_register),
+            format!("DT: {:#04x}", self.delay_timer),
+            format!("ST: {:#04x}", self.sound_timer),
+            format!("SP: {:#04x}", self.stack.len()),
+            format!("Mode: {:?}", self.mode),
+            format!("V0-7: {}", registers(0..8)),
+            format!("V8-F: {}", registers(8..16)),
+            format!("Stack: {}", stack),
+        ]
+        .join("\n")
+    }
+
+    /// The registers, timers, stack and display, for serializing.
+    pub fn state(&self) -> MachineState {
+        MachineState {
+            registers: self.registers,
+            index_register: self.index_register,
+            program_counter: self.program_counter,
+            stack_pointer: self.stack.len(),
+            stack: self.stack.clone(),
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+            mode: self.mode.clone(),
+            cycles: self.cycles,
+            display: self.display_bits(),
+        }
+    }
+
+    /// The display packed one bit per pixel, one row per `u64` with the leftmost pixel in
+    /// the highest bit.
+    pub fn display_bits(&self) -> [u64; 32] {
+        let mut bits = [0; 32];
+        for (packed, row) in bits.iter_mut().zip(self.display.iter()) {
+            *packed = row
+                .iter()
+                .fold(0, |packed, &pixel| packed << 1 | (pixel & 1) as u64);
+        }
+
+        bits
+    }
+
+    /// Disassembles the loaded program two bytes at a time, as (address, opcode, mnemonic).
+    pub fn instructions(&self) -> impl Iterator<Item = (usize, u16, String)> + '_ {
+        let start = self.load_address;
+        (start..start + self.program_size)
+            .step_by(2)
+            .map(move |address| {
+                let low = self.memory.get(address + 1).copied().unwrap_or(0);
+                let opcode = u16::from_be_bytes([self.memory[address], low]);
+                (address, opcode, decode(opcode).to_string())
+            })
     }
 
     pub fn fetch(&mut self) -> u16 {
@@ -84,314 +634,1535 @@ impl Chip8 {
         byte1 << 8 | byte2
     }
 
+    /// Run one full fetch, decode and execute cycle.
+    pub fn step(&mut self, keyboard_state: &KeyboardState) -> Result<Actions, Chip8Error> {
+        let address = self.program_counter;
+        let opcode = self.fetch();
+        self.cycles += 1;
+        let actions = self.execute(&decode(opcode), keyboard_state)?;
+        if self.strict && self.program_counter % 2 == 1 {
+            return Err(Chip8Error::MisalignedJump {
+                address,
+                target: self.program_counter,
+            });
+        }
+
+        Ok(actions)
+    }
+
+    /// Instructions stepped since the machine was created or reset, plus the extra each draw
+    /// costs under the `draw_cost` quirk.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// The emulated time those cycles take when running `speed` instructions per second.
+    pub fn elapsed(&self, speed: u64) -> Duration {
+        let speed = speed.max(1);
+        Duration::from_secs(self.cycles / speed)
+            + Duration::from_nanos((self.cycles % speed) * 1_000_000_000 / speed)
+    }
+
+    /// Runs up to `n` cycles of instructions, stopping early once waiting for a key or the next
+    /// vblank as nothing more can happen until then. Returns whether any of them changed the
+    /// display.
+    pub fn run_cycles(
+        &mut self,
+        n: usize,
+        keyboard_state: &KeyboardState,
+    ) -> Result<bool, Chip8Error> {
+        let mut redraw = false;
+        self.run_frame(n as u64, |machine| {
+            redraw |= matches!(machine.step(keyboard_state)?, Actions::Redraw);
+            Ok(())
+        })?;
+
+        Ok(redraw)
+    }
+
+    /// Calls `step`, which runs one instruction, until `cycles` are used up or the machine is
+    /// waiting for a key or the next vblank. Then nothing more can happen until the caller ticks
+    /// the timers for the next frame, so it needn't spin through the rest.
+    pub fn run_frame<E>(
+        &mut self,
+        cycles: u64,
+        mut step: impl FnMut(&mut Self) -> Result<(), E>,
+    ) -> Result<(), E> {
+        // Counted in cycles rather than steps, as draws can cost more than one
+        let end = self.cycles + cycles;
+        while self.cycles < end {
+            step(self)?;
+            if self.waiting_for_key || self.waiting_for_vblank {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn execute(
         &mut self,
-        operation: &Instruction,
+        opcode: &Opcode,
         keyboard_state: &KeyboardState,
-    ) -> Result<Actions, Error> {
-        match operation.instruction {
-            0x00 => match operation.nn {
-                0xE0 => {
-                    // Clear the display
-                    self.display = [[0; 64]; 32];
-                    return Ok(Actions::Redraw);
-                }
-                0xEE => {
-                    // Return from a subroutine
-                    if self.stack_pointer >= 0 {
-                        self.program_counter = self.stack[self.stack_pointer as usize];
-                        self.stack_pointer -= 1;
+    ) -> Result<Actions, Chip8Error> {
+        if let Some(counts) = self.profile.as_mut() {
+            counts[(opcode.encode() >> 12) as usize] += 1;
+        }
+
+        if let (Some(covered), Some(template)) = (self.coverage.as_mut(), opcode.template()) {
+            *covered |= 1 << template;
+        }
+
+        self.check_mode(opcode);
+
+        match *opcode {
+            _ if opcode.is_xo_chip() && !matches!(self.mode, Mode::XoChip) => {
+                self.ignore_unknown(opcode)?;
+            }
+            Opcode::MachineCall(0x000) if self.strict => {
+                // All zero, most likely uninitialized memory. Real hardware would call
+                // machine code at 0x000, so this is only an error in strict mode.
+                return Err(Chip8Error::Halt {
+                    address: self.program_counter - 2,
+                });
+            }
+            Opcode::MachineCall(nnn) => {
+                // Calls RCA 1802 program at address NNN, which only a host handler can run.
+                // The handler is taken out while it runs so it can borrow the machine.
+                if let Some(mut handler) = self.callbacks.on_machine_call.take() {
+                    let handled = handler(self, nnn);
+                    self.callbacks.on_machine_call.get_or_insert(handler);
+                    if handled {
+                        return Ok(Actions::None);
                     }
                 }
-                _ => {
-                    // Calls RCA 1802 program at address NNN
+
+                let address = self.program_counter - 2;
+                if self.strict {
+                    return Err(Chip8Error::MachineCall {
+                        target: nnn as usize,
+                        address,
+                    });
                 }
-            },
-            0x01 => {
+
+                log::trace!(
+                    "ignoring machine code call to {:#05x} at {:#06x}",
+                    nnn,
+                    address
+                );
+            }
+            Opcode::ClearScreen => {
+                // Clear the display
+                self.display = [[0; 64]; 32];
+                self.notify_draw();
+                return Ok(Actions::Redraw);
+            }
+            Opcode::Return => {
+                // Return from a subroutine
+                if let Some(address) = self.stack.pop() {
+                    self.program_counter = address;
+                    log::trace!(
+                        "return to {:#06x}, depth {}",
+                        self.program_counter,
+                        self.stack.len()
+                    );
+                }
+            }
+            Opcode::Jump(nnn) => {
                 // Jump to address NNN
-                self.program_counter = operation.nnn;
+                self.program_counter = nnn as usize;
             }
-            0x02 => {
+            Opcode::Call(nnn) => {
                 // Call subroutine at NNN
-                self.stack_pointer += 1;
-                self.stack[self.stack_pointer as usize] = self.program_counter;
-                self.program_counter = operation.nnn;
+                if self.stack.len() >= self.max_stack_depth {
+                    return Err(Chip8Error::StackOverflow {
+                        address: nnn as usize,
+                    });
+                }
+
+                self.stack.push(self.program_counter);
+                self.program_counter = nnn as usize;
+                log::trace!("call {:#06x}, depth {}", nnn, self.stack.len());
             }
-            0x03 => {
+            Opcode::SkipEqualByte(x, nn) => {
                 // Skip next instruction if Vx = NN
-                if self.registers[operation.x] == operation.nn {
-                    self.program_counter += 2;
+                if self.registers[x] == nn {
+                    self.skip();
                 }
             }
-            0x04 => {
+            Opcode::SkipNotEqualByte(x, nn) => {
                 // Skip next instruction if Vx != NN
-                if self.registers[operation.x] != operation.nn {
-                    self.program_counter += 2;
+                if self.registers[x] != nn {
+                    self.skip();
                 }
             }
-            0x05 => {
+            Opcode::SkipEqual(x, y) => {
                 // Skip next instruction if Vx = Vy
-                if self.registers[operation.x] == self.registers[operation.y] {
-                    self.program_counter += 2;
+                if self.registers[x] == self.registers[y] {
+                    self.skip();
                 }
             }
-            0x06 => {
+            Opcode::LoadByte(x, nn) => {
                 // Set Vx = NN
-                self.registers[operation.x] = operation.nn;
+                self.registers[x] = nn;
             }
-            0x07 => {
+            Opcode::AddByte(x, nn) => {
                 // Set Vx = Vx + NN
-                let (result, _) = self.registers[operation.x].overflowing_add(operation.nn);
-                self.registers[operation.x] = result;
+                self.registers[x] = self.registers[x].wrapping_add(nn);
             }
-            0x08 => match operation.n {
-                0x00 => {
-                    // Set Vx = Vy
-                    self.registers[operation.x] = self.registers[operation.y];
-                }
-                0x01 => {
-                    // Set Vx = Vx OR Vy
-                    self.registers[operation.x] |= self.registers[operation.y];
-                    match self.mode {
-                        Mode::Chip8 => {
-                            self.registers[0xf] = 0;
-                        }
-                        _ => {}
-                    }
-                }
-                0x02 => {
-                    // Set Vx = Vx AND Vy
-                    self.registers[operation.x] &= self.registers[operation.y];
-                    match self.mode {
-                        Mode::Chip8 => {
-                            self.registers[0xf] = 0;
-                        }
-                        _ => {}
-                    }
-                }
-                0x03 => {
-                    // Set Vx = Vx XOR Vy
-                    self.registers[operation.x] ^= self.registers[operation.y];
-                    match self.mode {
-                        Mode::Chip8 => {
-                            self.registers[0xf] = 0;
-                        }
-                        _ => {}
-                    }
-                }
-                0x04 => {
-                    // Set Vx = Vx + Vy, set VF = carry
-                    let (result, overflow) =
-                        self.registers[operation.x].overflowing_add(self.registers[operation.y]);
-                    self.registers[operation.x] = result;
-                    self.registers[0xF] = overflow as u8;
-                }
-                0x05 => {
-                    // Set Vx = Vx - Vy, set VF = NOT borrow
-                    let (result, overflow) =
-                        self.registers[operation.x].overflowing_sub(self.registers[operation.y]);
-                    self.registers[operation.x] = result;
-                    self.registers[0xF] = !overflow as u8;
-                }
-                0x06 => {
-                    match self.mode {
-                        Mode::Chip8 => {
-                            // Set Vx = Vy SHR 1
-                            self.registers[operation.x] = self.registers[operation.y];
-                        }
-                        Mode::Chip48 => {}
-                    }
-
-                    let carry = self.registers[operation.x] & 1;
-                    self.registers[operation.x] >>= 1;
-                    self.registers[0xF] = carry;
+            Opcode::Move(x, y) => {
+                // Set Vx = Vy
+                self.registers[x] = self.registers[y];
+            }
+            Opcode::Or(x, y) => {
+                // Set Vx = Vx OR Vy
+                self.registers[x] |= self.registers[y];
+                if self.quirks.vf_reset {
+                    self.registers[0xf] = 0;
                 }
-                0x07 => {
-                    // Set Vx = Vy - Vx, set VF = NOT borrow
-                    let (result, overflow) =
-                        self.registers[operation.y].overflowing_sub(self.registers[operation.x]);
-                    self.registers[operation.x] = result;
-                    self.registers[0xF] = !overflow as u8;
+            }
+            Opcode::And(x, y) => {
+                // Set Vx = Vx AND Vy
+                self.registers[x] &= self.registers[y];
+                if self.quirks.vf_reset {
+                    self.registers[0xf] = 0;
                 }
-                0x0E => {
-                    match self.mode {
-                        Mode::Chip8 => {
-                            // Set Vx = Vy SHL 1
-                            self.registers[operation.x] = self.registers[operation.y];
-                        }
-                        Mode::Chip48 => {}
-                    }
-
-                    let carry = self.registers[operation.x] >> 7;
-                    self.registers[operation.x] <<= 1;
-                    self.registers[0xF] = carry;
+            }
+            Opcode::Xor(x, y) => {
+                // Set Vx = Vx XOR Vy
+                self.registers[x] ^= self.registers[y];
+                if self.quirks.vf_reset {
+                    self.registers[0xf] = 0;
                 }
-                _ => {}
-            },
-            0x09 => {
+            }
+            Opcode::Add(x, y) => {
+                // Set Vx = Vx + Vy, set VF = carry
+                let (result, overflow) = self.registers[x].overflowing_add(self.registers[y]);
+                self.registers[x] = result;
+                self.registers[0xF] = overflow as u8;
+            }
+            Opcode::Sub(x, y) => {
+                // Set Vx = Vx - Vy, set VF = NOT borrow
+                let (result, overflow) = self.registers[x].overflowing_sub(self.registers[y]);
+                self.registers[x] = result;
+                self.registers[0xF] = !overflow as u8;
+            }
+            Opcode::ShiftRight(x, y) => {
+                // Set Vx = Vx SHR 1, or Vy SHR 1 with the shift_vy quirk. The source is read
+                // before either write and VF is written last, so VF holds the shifted out bit
+                // even when x or y is F.
+                let value = self.registers[if self.quirks.shift_vy { y } else { x }];
+                self.registers[x] = value >> 1;
+                self.registers[0xF] = value & 1;
+            }
+            Opcode::SubReverse(x, y) => {
+                // Set Vx = Vy - Vx, set VF = NOT borrow
+                let (result, overflow) = self.registers[y].overflowing_sub(self.registers[x]);
+                self.registers[x] = result;
+                self.registers[0xF] = !overflow as u8;
+            }
+            Opcode::ShiftLeft(x, y) => {
+                // Set Vx = Vx SHL 1, or Vy SHL 1 with the shift_vy quirk
+                let value = self.registers[if self.quirks.shift_vy { y } else { x }];
+                self.registers[x] = value << 1;
+                self.registers[0xF] = value >> 7;
+            }
+            Opcode::SkipNotEqual(x, y) => {
                 // Skip next instruction if Vx != Vy
-                if self.registers[operation.x] != self.registers[operation.y] {
-                    self.program_counter += 2;
+                if self.registers[x] != self.registers[y] {
+                    self.skip();
                 }
             }
-            0x0A => {
+            Opcode::LoadIndex(nnn) => {
                 // Set I = NNN
-                self.index_register = operation.nnn as u16;
+                self.index_register = nnn;
             }
-            0x0B => {
+            Opcode::JumpOffset(x, nnn) => {
                 // Jump to location NNN + V0
-                match self.mode {
-                    Mode::Chip8 => {
-                        self.program_counter = operation.nnn + self.registers[0] as usize;
-                    }
-                    Mode::Chip48 => {
-                        self.program_counter = operation.nnn + self.registers[operation.x] as usize;
-                    }
-                }
+                let offset = if self.quirks.jump_vx {
+                    self.registers[x]
+                } else {
+                    self.registers[0]
+                };
+                // Wrapped to the mode's memory, so the next fetch can't run off the end
+                self.program_counter = (nnn as usize + offset as usize) % self.memory.len();
             }
-            0x0C => {
+            Opcode::Random(x, nn) => {
                 // Set Vx = random byte AND NN
-                let number: u8 = rand::random();
-                self.registers[operation.x] = number & operation.nn;
-            }
-            0x0D => {
-                // Display
-                let x = (self.registers[operation.x] & 63) as usize;
-                let y = (self.registers[operation.y] & 31) as usize;
-                self.registers[0xF] = 0;
-                let sprite = &self.memory
-                    [self.index_register as usize..self.index_register as usize + operation.n];
-                for (j, byte) in sprite.iter().enumerate() {
-                    if y + j > 31 {
-                        break;
+                let number: u8 = self.rng.gen();
+                self.registers[x] = number & nn;
+            }
+            Opcode::Draw(x, y, n) => {
+                if self.quirks.display_wait {
+                    if self.drawn_this_frame {
+                        // Stall until the next frame by running this instruction again
+                        self.program_counter -= 2;
+                        self.waiting_for_vblank = true;
+                        return Ok(Actions::None);
                     }
 
-                    for i in 0..8 {
-                        if x + i > 63 {
-                            break;
-                        }
+                    self.drawn_this_frame = true;
+                }
+
+                if self.quirks.draw_cost {
+                    self.cycles += n as u64;
+                }
 
+                // Display. The starting position wraps around the screen, but the sprite
+                // itself is clipped at the right and bottom edges rather than wrapping.
+                let x = (self.registers[x] & 63) as usize;
+                let y = (self.registers[y] & 31) as usize;
+                // The sprite is read with wrapping at the end of the mode's memory, so I near
+                // the top can't read past it
+                let sprite: Vec<u8> = (0..n as usize)
+                    .map(|row| {
+                        self.memory[(self.index_register as usize + row) % self.memory.len()]
+                    })
+                    .collect();
+                let mut collided_rows = 0;
+                for (j, byte) in sprite.iter().take(32 - y).enumerate() {
+                    let mut collided = false;
+                    for i in 0..8.min(64 - x) {
                         let pixel = (byte >> (7 - i)) & 1;
                         if pixel == 1 {
-                            if self.display[y + j][x + i] == 1 {
-                                self.registers[0xF] = 1;
-                            }
-
+                            collided |= self.display[y + j][x + i] == 1;
                             self.display[y + j][x + i] ^= 1;
                         }
                     }
+
+                    collided_rows += collided as u8;
                 }
 
+                self.registers[0xF] = if self.quirks.collision_rows {
+                    let clipped_rows = sprite.len().saturating_sub(32 - y) as u8;
+                    collided_rows + clipped_rows
+                } else {
+                    (collided_rows > 0) as u8
+                };
+
+                self.notify_draw();
                 return Ok(Actions::Redraw);
             }
-            0x0E => match operation.nn {
-                0x9E => {
-                    // Skip next instruction if key with the value of Vx is pressed
-                    if keyboard_state.keys_pressed[self.registers[operation.x] as usize] {
-                        self.program_counter += 2;
-                    }
+            Opcode::SkipKeyPressed(x) => {
+                // Skip next instruction if key with the value of Vx is pressed. Only the low
+                // nibble selects a key, as on the VIP, so values above 0xF can't index past it.
+                if keyboard_state.keys_pressed[(self.registers[x] & 0xF) as usize] {
+                    self.skip();
                 }
-                0xA1 => {
-                    // Skip next instruction if key with the value of Vx is not pressed
-                    if !keyboard_state.keys_pressed[self.registers[operation.x] as usize] {
-                        self.program_counter += 2;
-                    }
-                }
-                _ => {}
-            },
-            0x0F => match operation.nn {
-                0x07 => {
-                    // Set Vx = delay timer value
-                    self.registers[operation.x] = self.delay_timer;
+            }
+            Opcode::SkipKeyNotPressed(x) => {
+                // Skip next instruction if key with the value of Vx is not pressed
+                if !keyboard_state.keys_pressed[(self.registers[x] & 0xF) as usize] {
+                    self.skip();
                 }
-                0x0A => {
-                    // Wait for a key press, store the value of the key in Vx
-                    if let Some(key) = keyboard_state.pressed_key {
-                        self.registers[operation.x] = key;
-                    } else {
+            }
+            Opcode::LoadDelay(x) => {
+                // Set Vx = delay timer value
+                self.registers[x] = self.delay_timer;
+            }
+            Opcode::WaitKey(x) => {
+                // Wait for a key to be pressed and released, then store it in Vx. Like the
+                // COSMAC VIP, the key is latched on press and only committed on release.
+                match self.latched_key {
+                    Some(key) if !keyboard_state.keys_pressed[key as usize] => {
+                        self.registers[x] = key;
+                        self.latched_key = None;
+                        self.waiting_for_key = false;
+                    }
+                    _ => {
+                        if self.latched_key.is_none() {
+                            self.latched_key = keyboard_state.pressed_key.map(|key| key & 0xF);
+                        }
+
                         self.program_counter -= 2;
+                        self.waiting_for_key = true;
                     }
                 }
-                0x15 => {
-                    // Set delay timer = Vx
-                    self.delay_timer = self.registers[operation.x];
-                }
-                0x18 => {
-                    // Set sound timer = Vx
-                    self.sound_timer = self.registers[operation.x];
+            }
+            Opcode::SetDelay(x) => {
+                // Set delay timer = Vx
+                self.delay_timer = self.registers[x];
+            }
+            Opcode::SetSound(x) => {
+                // Set sound timer = Vx
+                self.set_sound_timer(self.registers[x]);
+            }
+            Opcode::AddIndex(x) => {
+                // Set I = I + Vx
+                self.index_register = self.index_register.wrapping_add(self.registers[x] as u16);
+            }
+            Opcode::LoadFont(x) => {
+                // Set I = location of sprite for digit Vx
+                self.index_register = FONTSET_START_ADDRESS as u16 + self.registers[x] as u16 * 5;
+            }
+            Opcode::StoreBcd(x) => {
+                // Store BCD representation of Vx in memory locations I, I+1, and I+2
+                self.check_write(self.index_register as usize)?;
+                let value = self.registers[x];
+                for (i, digit) in [value / 100, (value / 10) % 10, value % 10]
+                    .into_iter()
+                    .enumerate()
+                {
+                    let address = self.index_address(i);
+                    self.memory[address] = digit;
                 }
-                0x1E => {
-                    // Set I = I + Vx
-                    self.index_register += self.registers[operation.x] as u16;
+            }
+            Opcode::StoreRegisters(x) => {
+                // Store registers V0 through Vx in memory starting at location I
+                self.check_write(self.index_register as usize)?;
+                for i in 0..=x {
+                    let address = self.index_address(i);
+                    self.memory[address] = self.registers[i];
                 }
-                0x29 => {
-                    // Set I = location of sprite for digit Vx
-                    self.index_register =
-                        FONTSET_START_ADDRESS as u16 + self.registers[operation.x] as u16 * 5;
+
+                if self.quirks.memory_increment {
+                    self.index_register = self.index_register.wrapping_add(x as u16 + 1);
                 }
-                0x33 => {
-                    // Store BCD representation of Vx in memory locations I, I+1, and I+2
-                    let value = self.registers[operation.x];
-                    self.memory[self.index_register as usize] = value / 100;
-                    self.memory[self.index_register as usize + 1] = (value / 10) % 10;
-                    self.memory[self.index_register as usize + 2] = value % 10;
+            }
+            Opcode::LoadRegisters(x) => {
+                // Read registers V0 through Vx from memory starting at location I
+                for i in 0..=x {
+                    self.registers[i] = self.memory[self.index_address(i)];
                 }
-                0x55 => {
-                    // Store registers V0 through Vx in memory starting at location I
-                    for i in 0..=operation.x {
-                        self.memory[self.index_register as usize + i] = self.registers[i];
-                    }
 
-                    match self.mode {
-                        Mode::Chip8 => {
-                            self.index_register += operation.x as u16 + 1;
-                        }
-                        Mode::Chip48 => {}
-                    }
+                if self.quirks.memory_increment {
+                    self.index_register = self.index_register.wrapping_add(x as u16 + 1);
                 }
-                0x65 => {
-                    // Read registers V0 through Vx from memory starting at location I
-                    for i in 0..=operation.x {
-                        self.registers[i] = self.memory[self.index_register as usize + i];
-                    }
-                    match self.mode {
-                        Mode::Chip8 => {
-                            self.index_register += operation.x as u16 + 1;
-                        }
-                        Mode::Chip48 => {}
-                    }
+            }
+            Opcode::StoreFlags(x) => {
+                // Store V0 through Vx in the RPL user flags, x <= 7
+                let count = x.min(7) + 1;
+                self.rpl_flags[..count].copy_from_slice(&self.registers[..count]);
+            }
+            Opcode::LoadFlags(x) => {
+                // Read V0 through Vx from the RPL user flags, x <= 7
+                let count = x.min(7) + 1;
+                self.registers[..count].copy_from_slice(&self.rpl_flags[..count]);
+            }
+            Opcode::LoadLongIndex => {
+                // Set I = the address in the next word, then skip over it
+                let pc = self.program_counter;
+                let operand = self
+                    .memory
+                    .get(pc..pc + 2)
+                    .ok_or(Chip8Error::AddressOutOfRange { address: pc })?;
+                self.index_register = u16::from_be_bytes([operand[0], operand[1]]);
+                self.program_counter += 2;
+            }
+            Opcode::LoadAudio => {
+                // Set the audio pattern to the 16 bytes starting at I
+                for (i, byte) in self.audio_pattern.iter_mut().enumerate() {
+                    *byte = self.memory[(self.index_register as usize + i) % self.memory.len()];
                 }
-                _ => {}
-            },
-            _ => {}
+            }
+            Opcode::SetPitch(x) => {
+                // Set the pitch = Vx
+                self.pitch = self.registers[x];
+            }
+            Opcode::Unknown(_) => self.ignore_unknown(opcode)?,
         }
 
         Ok(Actions::None)
     }
+
+    /// Errors on an opcode this mode doesn't have in strict mode, otherwise skips it.
+    fn ignore_unknown(&mut self, opcode: &Opcode) -> Result<(), Chip8Error> {
+        if self.strict {
+            return Err(Chip8Error::UnknownOpcode {
+                opcode: opcode.encode(),
+                address: self.program_counter - 2,
+            });
+        }
+
+        // Ignored, as most interpreters do
+        log::debug!(
+            "ignoring unknown opcode {:04X} at {:#06x}",
+            opcode.encode(),
+            self.program_counter - 2
+        );
+        self.unknown_opcode = Some((opcode.encode(), self.program_counter - 2));
+        Ok(())
+    }
 }
 
-pub struct Instruction {
-    instruction: u8,
-    x: usize,
-    y: usize,
-    n: usize,
-    nn: u8,
-    nnn: usize,
+/// Unpacks `Chip8::display_bits` back into one byte per pixel.
+pub fn unpack_display(bits: &[u64; 32]) -> [[u8; 64]; 32] {
+    let mut display = [[0; 64]; 32];
+    for (row, packed) in display.iter_mut().zip(bits.iter()) {
+        for (x, pixel) in row.iter_mut().enumerate() {
+            *pixel = (packed >> (63 - x)) as u8 & 1;
+        }
+    }
+
+    display
 }
 
-pub fn decode(opcode: u16) -> Instruction {
-    let instruction = ((opcode & 0xF000) >> 12) as u8;
-    let x = ((opcode & 0x0F00) >> 8) as usize;
-    let y = ((opcode & 0x00F0) >> 4) as usize;
-    let n = (opcode & 0x000F) as usize;
-    let nn = (opcode & 0x00FF) as u8;
-    let nnn = (opcode & 0x0FFF) as usize;
-    return Instruction {
-        instruction,
-        x,
-        y,
-        n,
-        nn,
-        nnn,
-    };
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(machine: &mut Chip8, opcode: u16) -> Actions {
+        machine
+            .execute(&decode(opcode), &KeyboardState::new())
+            .unwrap()
+    }
+
+    #[test]
+    fn decode_round_trips_every_opcode() {
+        assert_eq!(decode(0xD12F), Opcode::Draw(1, 2, 0xF));
+        assert_eq!(decode(0x5121), Opcode::Unknown(0x5121));
+        for opcode in 0..=u16::MAX {
+            assert_eq!(decode(opcode).encode(), opcode);
+        }
+    }
+
+    #[test]
+    fn mnemonics_assemble_back_to_the_same_opcode() {
+        for opcode in [
+            0x00E0, 0x1234, 0x6A2F, 0x8AB6, 0xB123, 0xD125, 0xF30A, 0xF555, 0xF000, 0xF002, 0xF13A,
+        ] {
+            let source = decode(opcode).to_string();
+            assert_eq!(
+                crate::asm::assemble(&source).unwrap(),
+                opcode.to_be_bytes(),
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_opcodes_are_errors_only_in_strict_mode() {
+        let mut machine = Chip8::new(Mode::Chip8);
+        machine.load(&[0xE1, 0x23, 0xE1, 0x23]).unwrap();
+        machine.step(&KeyboardState::new()).unwrap();
+
+        machine.strict = true;
+        assert_eq!(
+            machine.step(&KeyboardState::new()).err(),
+            Some(Chip8Error::UnknownOpcode {
+                opcode: 0xE123,
+                address: PROGRAM_START_ADDRESS + 2
+            })
+        );
+    }
+
+    #[test]
+    fn step_runs_the_instruction_at_the_program_counter() {
+        let mut machine = Chip8::new(Mode::Chip8);
+        machine.load(&[0x60, 0x2A, 0x12, 0x00]).unwrap();
+        machine.step(&KeyboardState::new()).unwrap();
+        assert_eq!(machine.registers[0], 0x2A);
+        machine.step(&KeyboardState::new()).unwrap();
+        assert_eq!(machine.program_counter, PROGRAM_START_ADDRESS);
+    }
+
+    #[test]
+    fn fetch_sees_code_written_by_the_program() {
+        let mut machine = Chip8::new(Mode::Chip48);
+        // Store V0-V1 over the zeroes at 0x20A, turning them into LD VA, 0x2A
+        let program = crate::asm::assemble(
+            "
+            LD V0, 0x6A
+            LD V1, 0x2A
+            LD I, patch
+            LD [I], V1
+            LD VB, 0
+            patch: DW 0
+            ",
+        )
+        .unwrap();
+        machine.load(&program).unwrap();
+        for _ in 0..5 {
+            machine.step(&KeyboardState::new()).unwrap();
+        }
+
+        assert_eq!(machine.fetch(), 0x6A2A);
+        machine.program_counter -= 2;
+        machine.step(&KeyboardState::new()).unwrap();
+        assert_eq!(machine.registers[0xA], 0x2A);
+    }
+
+    #[test]
+    fn programs_load_and_start_at_the_load_address() {
+        let mut machine = Chip8::new(Mode::Chip8);
+        machine.set_load_address(0x600).unwrap();
+        machine.load(&[0x60, 0x2A]).unwrap();
+        assert_eq!(machine.program_counter, 0x600);
+        assert_eq!(machine.memory[0x600..0x602], [0x60, 0x2A]);
+
+        let result = machine.load(&[0; 4096 - 0x600 + 1]);
+        assert_eq!(
+            result,
+            Err(Chip8Error::ProgramTooLarge {
+                size: 4096 - 0x600 + 1,
+                available: 4096 - 0x600
+            })
+        );
+    }
+
+    #[test]
+    fn fonts_replace_the_built_in_glyphs() {
+        let mut machine = Chip8::new(Mode::Chip8);
+        machine.load_font(&[0xAA; 10]).unwrap();
+        assert_eq!(machine.memory[0x50..0x5A], [0xAA; 10]);
+        assert_eq!(machine.memory[0x5A], FONTSET[10]);
+
+        assert_eq!(
+            machine.load_font(&[0; 7]),
+            Err(Chip8Error::InvalidFont { size: 7 })
+        );
+        assert_eq!(
+            machine.load_font(&[0; 85]),
+            Err(Chip8Error::InvalidFont { size: 85 })
+        );
+    }
+
+    #[test]
+    fn instructions_cover_only_the_loaded_program() {
+        let mut machine = Chip8::new(Mode::Chip8);
+        machine.load(&[0x00, 0xE0, 0x12, 0x00, 0xAB]).unwrap();
+        let instructions: Vec<_> = machine.instructions().collect();
+        assert_eq!(
+            instructions,
+            [
+                (0x200, 0x00E0, "CLS".to_string()),
+                (0x202, 0x1200, "JP 0x200".to_string()),
+                (0x204, 0xAB00, "LD I, 0xB00".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn load_at_places_data_anywhere_in_memory() {
+        let mut machine = Chip8::new(Mode::Chip8);
+        machine.load_at(0xFFE, &[1, 2]).unwrap();
+        assert_eq!(machine.memory[0xFFE..], [1, 2]);
+        assert_eq!(machine.program_counter, PROGRAM_START_ADDRESS);
+
+        assert_eq!(
+            machine.load_at(0xFFF, &[1, 2]),
+            Err(Chip8Error::ProgramTooLarge {
+                size: 2,
+                available: 1
+            })
+        );
+        assert_eq!(
+            machine.load_at(0x1000, &[]),
+            Err(Chip8Error::AddressOutOfRange { address: 0x1000 })
+        );
+    }
+
+    #[test]
+    fn zero_opcode_halts_only_in_strict_mode() {
+        let mut machine = Chip8::new(Mode::Chip8);
+        machine.step(&KeyboardState::new()).unwrap();
+        assert_eq!(machine.program_counter, PROGRAM_START_ADDRESS + 2);
+
+        machine.strict = true;
+        let result = machine.step(&KeyboardState::new());
+        assert_eq!(
+            result.err(),
+            Some(Chip8Error::Halt {
+                address: PROGRAM_START_ADDRESS + 2
+            })
+        );
+    }
+
+    #[test]
+    fn odd_jumps_are_errors_only_in_strict_mode() {
+        let mut machine = Chip8::new(Mode::Chip8);
+        machine.load(&[0x12, 0x03, 0x00, 0x00]).unwrap();
+        machine.step(&KeyboardState::new()).unwrap();
+        assert_eq!(machine.program_counter, 0x203);
+
+        machine.strict = true;
+        machine.program_counter = PROGRAM_START_ADDRESS;
+        assert_eq!(
+            machine.step(&KeyboardState::new()).err(),
+            Some(Chip8Error::MisalignedJump {
+                address: PROGRAM_START_ADDRESS,
+                target: 0x203
+            })
+        );
+    }
+
+    #[test]
+    fn calling_with_a_full_stack_overflows() {
+        let mut machine = Chip8::new(Mode::Chip8);
+        for _ in 0..16 {
+            run(&mut machine, 0x2300);
+        }
+
+        let result = machine.execute(&decode(0x2300), &KeyboardState::new());
+        assert_eq!(
+            result.err(),
+            Some(Chip8Error::StackOverflow { address: 0x300 })
+        );
+    }
+
+    #[test]
+    fn stack_depth_is_configurable() {
+        let mut machine = Chip8::new(Mode::Chip48);
+        machine.max_stack_depth = 32;
+        for _ in 0..32 {
+            run(&mut machine, 0x2300);
+        }
+        assert_eq!(machine.stack.len(), 32);
+        assert!(machine
+            .execute(&decode(0x2300), &KeyboardState::new())
+            .is_err());
+
+        run(&mut machine, 0x00EE);
+        assert_eq!(machine.stack.len(), 31);
+        assert_eq!(machine.program_counter, 0x300);
+    }
+
+    #[test]
+    fn callbacks_fire_on_draw_and_sound_changes() {
+        use std::{cell::RefCell, rc::Rc};
+
+        let draws = Rc::new(RefCell::new(0));
+        let sounds = Rc::new(RefCell::new(Vec::new()));
+        let mut machine = Chip8::new(Mode::Chip8);
+        let draw_count = draws.clone();
+        machine.set_on_draw(Box::new(move |_| *draw_count.borrow_mut() += 1));
+        let sound_log = sounds.clone();
+        machine.set_on_sound(Box::new(move |on| sound_log.borrow_mut().push(on)));
+
+        run(&mut machine, 0x00E0);
+        run(&mut machine, 0xD015);
+        assert_eq!(*draws.borrow(), 2);
+
+        machine.registers[0] = 5;
+        run(&mut machine, 0xF018);
+        run(&mut machine, 0xF018);
+        machine.registers[0] = 0;
+        run(&mut machine, 0xF018);
+        assert_eq!(*sounds.borrow(), [true, false]);
+    }
+
+    #[test]
+    fn skipped_unknown_opcodes_are_reported_once() {
+        let mut machine = Chip8::new(Mode::Chip8);
+        machine.program_counter += 2;
+        machine
+            .execute(&decode(0x5001), &KeyboardState::new())
+            .unwrap();
+        assert_eq!(
+            machine.take_unknown_opcode(),
+            Some((0x5001, PROGRAM_START_ADDRESS))
+        );
+        assert_eq!(machine.take_unknown_opcode(), None);
+    }
+
+    #[test]
+    fn machine_calls_go_to_the_handler_or_error_when_strict() {
+        let mut machine = Chip8::new(Mode::Chip8);
+        machine.strict = true;
+        machine.program_counter += 2;
+        assert_eq!(
+            machine
+                .execute(&decode(0x0123), &KeyboardState::new())
+                .err(),
+            Some(Chip8Error::MachineCall {
+                target: 0x123,
+                address: PROGRAM_START_ADDRESS
+            })
+        );
+
+        machine.set_on_machine_call(Box::new(|machine, nnn| {
+            machine.registers[0] = nnn as u8;
+            nnn == 0x123
+        }));
+        run(&mut machine, 0x0123);
+        assert_eq!(machine.registers[0], 0x23);
+    }
+
+    #[test]
+    fn builder_configures_and_loads() {
+        let program = [0xC0, 0xFF];
+        let mut machine = Chip8::builder()
+            .mode(Mode::XoChip)
+            .quirks(Platform::Octo.quirks())
+            .seed(7)
+            .load_address(0x300)
+            .load(&program)
+            .build()
+            .unwrap();
+        let mut same = Chip8::builder()
+            .mode(Mode::XoChip)
+            .seed(7)
+            .load_address(0x300)
+            .load(&program)
+            .build()
+            .unwrap();
+        assert_eq!(machine.memory.len(), XO_CHIP_MEMORY_SIZE);
+        assert_eq!(machine.program_counter, 0x300);
+        machine.step(&KeyboardState::new()).unwrap();
+        same.step(&KeyboardState::new()).unwrap();
+        assert_eq!(machine.registers[0], same.registers[0]);
+
+        assert_eq!(
+            Chip8::try_from(&[0u8; MEMORY_SIZE][..]).err(),
+            Some(Chip8Error::ProgramTooLarge {
+                size: MEMORY_SIZE,
+                available: MEMORY_SIZE - PROGRAM_START_ADDRESS
+            })
+        );
+    }
+
+    #[test]
+    fn counts_cycles_into_emulated_time() {
+        let mut machine = Chip8::new(Mode::Chip8);
+        machine.load(&[0x12, 0x00]).unwrap();
+        machine.run_cycles(1050, &KeyboardState::new()).unwrap();
+        assert_eq!(machine.cycles(), 1050);
+        assert_eq!(machine.elapsed(700), Duration::from_millis(1500));
+
+        machine.reset();
+        assert_eq!(machine.cycles(), 0);
+    }
+
+    #[test]
+    fn tick_timers_counts_down_to_zero() {
+        let mut machine = Chip8::new(Mode::Chip8);
+        machine.delay_timer = 1;
+        machine.sound_timer = 2;
+        assert!(machine.tick_timers());
+        assert_eq!(machine.delay_timer, 0);
+        assert!(!machine.tick_timers());
+        assert!(!machine.tick_timers());
+        assert_eq!((machine.delay_timer, machine.sound_timer), (0, 0));
+    }
+
+    #[test]
+    fn draws_coalesce_into_one_redraw() {
+        let mut machine = Chip8::new(Mode::Chip8);
+        assert!(!machine.needs_redraw());
+        run(&mut machine, 0x00E0);
+        run(&mut machine, 0xD015);
+        assert!(machine.take_redraw());
+        assert!(!machine.take_redraw());
+    }
+
+    #[test]
+    fn display_wait_stalls_a_second_draw_until_vblank() {
+        let mut machine = Chip8::new(Mode::Chip8);
+        machine.load(&[0xD0, 0x15, 0xD0, 0x15]).unwrap();
+        machine.step(&KeyboardState::new()).unwrap();
+        machine.step(&KeyboardState::new()).unwrap();
+        assert!(machine.is_waiting_for_vblank());
+        assert_eq!(machine.program_counter, PROGRAM_START_ADDRESS + 2);
+
+        machine.vblank();
+        machine.step(&KeyboardState::new()).unwrap();
+        assert!(!machine.is_waiting_for_vblank());
+        assert_eq!(machine.program_counter, PROGRAM_START_ADDRESS + 4);
+
+        machine.quirks.display_wait = false;
+        machine
+            .execute(&decode(0xD015), &KeyboardState::new())
+            .unwrap();
+        assert!(!machine.is_waiting_for_vblank());
+    }
+
+    #[test]
+    fn draw_cost_spends_the_budget_on_sprite_rows() {
+        for (draw_cost, program_counter) in [(true, 2), (false, 6)] {
+            let mut machine = Chip8::new(Mode::Chip48);
+            machine.quirks.draw_cost = draw_cost;
+            machine.load(&[0xD0, 0x15, 0x60, 0x01, 0x61, 0x01]).unwrap();
+            // With the cost, the draw's one cycle and five rows use up the whole budget
+            machine.run_cycles(3, &KeyboardState::new()).unwrap();
+            assert_eq!(
+                machine.program_counter,
+                PROGRAM_START_ADDRESS + program_counter
+            );
+        }
+    }
+
+    #[test]
+    fn rpl_flags_round_trip_and_clamp_to_eight() {
+        let mut machine = Chip8::new(Mode::Chip48);
+        machine.registers = core::array::from_fn(|i| i as u8 + 1);
+        run(&mut machine, 0xFF75);
+        assert_eq!(machine.rpl_flags, [1, 2, 3, 4, 5, 6, 7, 8]);
+
+        machine.registers = [0; 16];
+        run(&mut machine, 0xF285);
+        assert_eq!(machine.registers[..4], [1, 2, 3, 0]);
+    }
+
+    #[test]
+    fn add_registers_sets_carry() {
+        let mut machine = Chip8::new(Mode::Chip8);
+        machine.registers[0] = 0xF0;
+        machine.registers[1] = 0x20;
+        run(&mut machine, 0x8014);
+        assert_eq!(machine.registers[0], 0x10);
+        assert_eq!(machine.registers[0xF], 1);
+
+        run(&mut machine, 0x8014);
+        assert_eq!(machine.registers[0], 0x30);
+        assert_eq!(machine.registers[0xF], 0);
+    }
+
+    #[test]
+    fn subtract_registers_sets_not_borrow() {
+        let mut machine = Chip8::new(Mode::Chip8);
+        machine.registers[0] = 0x10;
+        machine.registers[1] = 0x20;
+        run(&mut machine, 0x8015);
+        assert_eq!(machine.registers[0], 0xF0);
+        assert_eq!(machine.registers[0xF], 0);
+
+        machine.registers[0] = 0x30;
+        run(&mut machine, 0x8015);
+        assert_eq!(machine.registers[0], 0x10);
+        assert_eq!(machine.registers[0xF], 1);
+
+        // Equal operands don't borrow
+        machine.registers[0] = 0x20;
+        run(&mut machine, 0x8015);
+        assert_eq!(machine.registers[0], 0);
+        assert_eq!(machine.registers[0xF], 1);
+    }
+
+    #[test]
+    fn reverse_subtract_registers_sets_not_borrow() {
+        let mut machine = Chip8::new(Mode::Chip8);
+        machine.registers[0] = 0x10;
+        machine.registers[1] = 0x30;
+        run(&mut machine, 0x8017);
+        assert_eq!(machine.registers[0], 0x20);
+        assert_eq!(machine.registers[0xF], 1);
+
+        machine.registers[0] = 0x40;
+        run(&mut machine, 0x8017);
+        assert_eq!(machine.registers[0], 0xF0);
+        assert_eq!(machine.registers[0xF], 0);
+
+        // Equal operands don't borrow
+        machine.registers[0] = 0x30;
+        run(&mut machine, 0x8017);
+        assert_eq!(machine.registers[0], 0);
+        assert_eq!(machine.registers[0xF], 1);
+    }
+
+    #[test]
+    fn shifts_use_vy_in_chip8_mode() {
+        let mut machine = Chip8::new(Mode::Chip8);
+        machine.registers[0] = 0xFF;
+        machine.registers[1] = 0b0000_0011;
+        run(&mut machine, 0x8016);
+        assert_eq!(machine.registers[0], 0b0000_0001);
+        assert_eq!(machine.registers[0xF], 1);
+
+        machine.registers[1] = 0b1000_0001;
+        run(&mut machine, 0x801E);
+        assert_eq!(machine.registers[0], 0b0000_0010);
+        assert_eq!(machine.registers[0xF], 1);
+    }
+
+    #[test]
+    fn shifts_are_in_place_in_chip48_mode() {
+        let mut machine = Chip8::new(Mode::Chip48);
+        machine.registers[0] = 0b0000_0010;
+        machine.registers[1] = 0xFF;
+        run(&mut machine, 0x8016);
+        assert_eq!(machine.registers[0], 0b0000_0001);
+        assert_eq!(machine.registers[0xF], 0);
+
+        machine.registers[0] = 0b0100_0000;
+        run(&mut machine, 0x801E);
+        assert_eq!(machine.registers[0], 0b1000_0000);
+        assert_eq!(machine.registers[0xF], 0);
+    }
+
+    #[test]
+    fn shifts_into_vf_leave_the_shifted_out_bit() {
+        for mode in [Mode::Chip8, Mode::Chip48] {
+            let mut machine = Chip8::new(mode);
+            machine.registers[0xF] = 0b1000_0011;
+            machine.registers[1] = 0b1000_0011;
+            run(&mut machine, 0x8F16);
+            assert_eq!(machine.registers[0xF], 1);
+
+            machine.registers[0xF] = 0b0100_0000;
+            machine.registers[1] = 0b0100_0000;
+            run(&mut machine, 0x8F1E);
+            assert_eq!(machine.registers[0xF], 0);
+        }
+    }
+
+    #[test]
+    fn shifts_from_vf_use_its_value_before_the_carry() {
+        let mut machine = Chip8::new(Mode::Chip8);
+        machine.registers[0xF] = 0b1000_0011;
+        run(&mut machine, 0x81F6);
+        assert_eq!(machine.registers[1], 0b0100_0001);
+        assert_eq!(machine.registers[0xF], 1);
+
+        machine.registers[0xF] = 0b1000_0011;
+        run(&mut machine, 0x81FE);
+        assert_eq!(machine.registers[1], 0b0000_0110);
+        assert_eq!(machine.registers[0xF], 1);
+    }
+
+    #[test]
+    fn skip_if_equal_to_byte() {
+        let mut machine = Chip8::new(Mode::Chip8);
+        machine.registers[3] = 0x42;
+        run(&mut machine, 0x3342);
+        assert_eq!(machine.program_counter, PROGRAM_START_ADDRESS + 2);
+
+        run(&mut machine, 0x3343);
+        assert_eq!(machine.program_counter, PROGRAM_START_ADDRESS + 2);
+    }
+
+    #[test]
+    fn skip_if_registers_equal_or_not() {
+        let mut machine = Chip8::new(Mode::Chip8);
+        machine.registers[1] = 7;
+        machine.registers[2] = 7;
+        run(&mut machine, 0x5120);
+        assert_eq!(machine.program_counter, PROGRAM_START_ADDRESS + 2);
+        run(&mut machine, 0x9120);
+        assert_eq!(machine.program_counter, PROGRAM_START_ADDRESS + 2);
+
+        machine.registers[2] = 8;
+        run(&mut machine, 0x5120);
+        assert_eq!(machine.program_counter, PROGRAM_START_ADDRESS + 2);
+        run(&mut machine, 0x9120);
+        assert_eq!(machine.program_counter, PROGRAM_START_ADDRESS + 4);
+    }
+
+    #[test]
+    fn key_skips_use_the_low_nibble_of_vx() {
+        let mut machine = Chip8::new(Mode::Chip8);
+        machine.registers[1] = 0x20;
+        let mut keyboard = KeyboardState::new();
+        keyboard.keys_pressed[0] = true;
+        machine.execute(&decode(0xE19E), &keyboard).unwrap();
+        assert_eq!(machine.program_counter, PROGRAM_START_ADDRESS + 2);
+    }
+
+    #[test]
+    fn warns_once_about_opcodes_from_other_modes() {
+        let mut machine = Chip8::new(Mode::Chip8);
+        machine.load(&[0xF1, 0x75, 0x00, 0xFF]).unwrap();
+        machine.step(&KeyboardState::new()).unwrap();
+        let warning = machine.take_mode_warning().unwrap();
+        assert!(warning.starts_with("F175 at 0x0200"), "{}", warning);
+
+        machine.step(&KeyboardState::new()).unwrap();
+        assert!(machine.take_mode_warning().is_none());
+
+        let mut machine = Chip8::new(Mode::Chip48);
+        machine.load(&[0xF1, 0x75]).unwrap();
+        machine.step(&KeyboardState::new()).unwrap();
+        assert!(machine.take_mode_warning().is_none());
+    }
+
+    #[test]
+    fn run_cycles_stops_at_a_key_wait() {
+        let mut machine = Chip8::new(Mode::Chip48);
+        machine.load(&[0xD0, 0x15, 0xF0, 0x0A]).unwrap();
+        assert!(machine.run_cycles(100, &KeyboardState::new()).unwrap());
+        assert!(machine.is_waiting_for_key());
+        assert_eq!(machine.program_counter, PROGRAM_START_ADDRESS + 2);
+
+        assert!(!machine.run_cycles(100, &KeyboardState::new()).unwrap());
+    }
+
+    #[test]
+    fn jump_with_offset_wraps_around_memory() {
+        let mut machine = Chip8::new(Mode::Chip8);
+        machine.registers[0] = 0x10;
+        machine.registers[0xF] = 0x20;
+        run(&mut machine, 0xBFF8);
+        assert_eq!(machine.program_counter, 0x008);
+
+        let mut machine = Chip8::new(Mode::Chip48);
+        machine.registers[0] = 0x10;
+        machine.registers[0xF] = 0x20;
+        run(&mut machine, 0xBFF8);
+        assert_eq!(machine.program_counter, 0x018);
+
+        // XO-CHIP's 64KB is big enough that nothing wraps
+        let mut machine = Chip8::new(Mode::XoChip);
+        machine.registers[0] = 0x10;
+        run(&mut machine, 0xBFF8);
+        assert_eq!(machine.program_counter, 0x1008);
+    }
+
+    #[test]
+    fn wait_for_key_commits_on_release() {
+        let mut machine = Chip8::new(Mode::Chip8);
+        machine.load(&[0xF3, 0x0A]).unwrap();
+        let mut keyboard = KeyboardState::new();
+        machine.step(&keyboard).unwrap();
+        assert_eq!(machine.program_counter, PROGRAM_START_ADDRESS);
+
+        keyboard.keys_pressed[7] = true;
+        keyboard.pressed_key = Some(7);
+        machine.step(&keyboard).unwrap();
+        keyboard.pressed_key = None;
+        machine.step(&keyboard).unwrap();
+        assert_eq!(machine.program_counter, PROGRAM_START_ADDRESS);
+        assert_eq!(machine.registers[3], 0);
+
+        keyboard.keys_pressed[7] = false;
+        machine.step(&keyboard).unwrap();
+        assert_eq!(machine.program_counter, PROGRAM_START_ADDRESS + 2);
+        assert_eq!(machine.registers[3], 7);
+    }
+
+    #[test]
+    fn frames_end_early_while_timers_count_down_waiting_for_a_key() {
+        let mut machine = Chip8::new(Mode::Chip8);
+        machine.load(&[0xF0, 0x0A]).unwrap();
+        machine.delay_timer = 3;
+        machine.sound_timer = 2;
+        for _ in 0..3 {
+            let mut steps = 0;
+            machine
+                .run_frame(11, |machine| {
+                    steps += 1;
+                    machine.step(&KeyboardState::new()).map(|_| ())
+                })
+                .unwrap();
+            assert_eq!(steps, 1);
+            assert!(machine.is_waiting_for_key());
+            machine.tick_timers();
+        }
+
+        assert_eq!((machine.delay_timer, machine.sound_timer), (0, 0));
+        assert_eq!(machine.program_counter, PROGRAM_START_ADDRESS);
+    }
+
+    #[test]
+    fn protected_interpreter_rejects_writes_to_the_font() {
+        let mut machine = Chip8::new(Mode::Chip8);
+        machine.load(&[0xF5, 0x33]).unwrap();
+        machine.registers[5] = 137;
+        machine.index_register = FONTSET_START_ADDRESS as u16;
+        machine.protect_interpreter = true;
+        assert_eq!(
+            machine.step(&KeyboardState::new()).err(),
+            Some(Chip8Error::ProtectedWrite {
+                address: PROGRAM_START_ADDRESS,
+                target: FONTSET_START_ADDRESS
+            })
+        );
+        assert_eq!(machine.memory[FONTSET_START_ADDRESS], FONTSET[0]);
+    }
+
+    #[test]
+    fn leaving_xo_chip_wraps_addresses_into_the_smaller_memory() {
+        let mut machine = Chip8::new(Mode::XoChip);
+        machine.program_counter = 0x1234;
+        machine.index_register = 0xF300;
+        machine.stack.push(0x2202);
+        machine.set_mode(Mode::Chip8);
+        assert_eq!(machine.memory.len(), MEMORY_SIZE);
+        assert_eq!(machine.program_counter, 0x234);
+        assert_eq!(machine.index_register, 0x300);
+        assert_eq!(machine.stack, [0x202]);
+
+        machine.step(&KeyboardState::new()).unwrap();
+        run(&mut machine, 0xFF65);
+        run(&mut machine, 0x00EE);
+        assert_eq!(machine.program_counter, 0x202);
+    }
+
+    #[test]
+    fn platforms_pick_their_mode_and_quirks() {
+        let legacy = Platform::SchipLegacy;
+        let mut machine = Chip8::new(legacy.mode());
+        machine.quirks = legacy.quirks();
+        assert_eq!(machine.memory.len(), MEMORY_SIZE);
+        assert!(machine.quirks.display_wait && machine.quirks.jump_vx);
+
+        assert_eq!(Platform::Octo.mode().memory_size(), MEMORY_SIZE);
+        assert_eq!(Platform::XoChip.mode().memory_size(), XO_CHIP_MEMORY_SIZE);
+    }
+
+    #[test]
+    fn xo_chip_loads_long_addresses_into_its_bigger_memory() {
+        let mut machine = Chip8::new(Mode::XoChip);
+        assert_eq!(machine.memory.len(), XO_CHIP_MEMORY_SIZE);
+        machine.load(&[0xF0, 0x00, 0xFF, 0x00]).unwrap();
+        machine.step(&KeyboardState::new()).unwrap();
+        assert_eq!(machine.index_register, 0xFF00);
+        assert_eq!(machine.program_counter, PROGRAM_START_ADDRESS + 4);
+
+        machine.set_mode(Mode::Chip8);
+        assert_eq!(machine.memory.len(), MEMORY_SIZE);
+        machine.program_counter = PROGRAM_START_ADDRESS;
+        machine.step(&KeyboardState::new()).unwrap();
+        assert_eq!(machine.program_counter, PROGRAM_START_ADDRESS + 2);
+    }
+
+    #[test]
+    fn xo_chip_sets_the_audio_pattern_and_pitch() {
+        let mut machine = Chip8::new(Mode::XoChip);
+        assert_eq!(machine.pitch(), DEFAULT_PITCH);
+        machine.memory[0x300..0x310].copy_from_slice(&[0xAA; 16]);
+        machine.index_register = 0x300;
+        machine.registers[2] = 112;
+        run(&mut machine, 0xF002);
+        run(&mut machine, 0xF23A);
+        assert_eq!(machine.audio_pattern(), &[0xAA; 16]);
+        assert_eq!(machine.pitch(), 112);
+
+        assert!(!machine.is_beeping());
+        run(&mut machine, 0xF218);
+        assert!(machine.is_beeping());
+    }
+
+    #[test]
+    fn store_bcd() {
+        let mut machine = Chip8::new(Mode::Chip8);
+        machine.registers[5] = 137;
+        machine.index_register = 0x300;
+        run(&mut machine, 0xF533);
+        assert_eq!(machine.memory[0x300..0x303], [1, 3, 7]);
+    }
+
+    #[test]
+    fn store_and_load_registers_increment_i_in_chip8_mode() {
+        let mut machine = Chip8::new(Mode::Chip8);
+        machine.registers[..3].copy_from_slice(&[1, 2, 3]);
+        machine.index_register = 0x300;
+        run(&mut machine, 0xF255);
+        assert_eq!(machine.memory[0x300..0x303], [1, 2, 3]);
+        assert_eq!(machine.index_register, 0x303);
+
+        machine.index_register = 0x300;
+        machine.registers = [0; 16];
+        run(&mut machine, 0xF265);
+        assert_eq!(machine.registers[..3], [1, 2, 3]);
+        assert_eq!(machine.index_register, 0x303);
+    }
+
+    #[test]
+    fn store_and_load_registers_leave_i_in_chip48_mode() {
+        let mut machine = Chip8::new(Mode::Chip48);
+        machine.registers[..3].copy_from_slice(&[1, 2, 3]);
+        machine.index_register = 0x300;
+        run(&mut machine, 0xF255);
+        assert_eq!(machine.index_register, 0x300);
+
+        machine.registers = [0; 16];
+        run(&mut machine, 0xF265);
+        assert_eq!(machine.registers[..3], [1, 2, 3]);
+        assert_eq!(machine.index_register, 0x300);
+    }
+
+    #[test]
+    fn draw_sets_vf_on_collision() {
+        let mut machine = Chip8::new(Mode::Chip8);
+        machine.index_register = FONTSET_START_ADDRESS as u16;
+        let action = run(&mut machine, 0xD015);
+        assert!(matches!(action, Actions::Redraw));
+        assert_eq!(machine.registers[0xF], 0);
+        assert_eq!(machine.display[0][..4], [1, 1, 1, 1]);
+
+        machine.vblank();
+        run(&mut machine, 0xD015);
+        assert_eq!(machine.registers[0xF], 1);
+        assert!(machine.display.iter().flatten().all(|&pixel| pixel == 0));
+    }
+
+    fn lit_pixels(machine: &Chip8) -> Vec<(usize, usize)> {
+        let mut pixels = Vec::new();
+        for (y, row) in machine.display.iter().enumerate() {
+            for (x, &pixel) in row.iter().enumerate() {
+                if pixel == 1 {
+                    pixels.push((x, y));
+                }
+            }
+        }
+
+        pixels
+    }
+
+    #[test]
+    fn draw_clips_sprites_at_the_right_and_bottom_edges() {
+        let mut machine = Chip8::new(Mode::Chip48);
+        machine.memory[0x300] = 0xFF;
+        machine.memory[0x301] = 0xFF;
+        machine.index_register = 0x300;
+        machine.registers[0] = 62;
+        machine.registers[1] = 31;
+        run(&mut machine, 0xD012);
+        assert_eq!(lit_pixels(&machine), [(62, 31), (63, 31)]);
+        // Nothing collided, but SUPER-CHIP counts the row clipped at the bottom
+        assert_eq!(machine.registers[0xF], 1);
+    }
+
+    #[test]
+    fn sprites_straddling_the_bottom_edge_count_rows_outside_chip8() {
+        // Two rows collide and two are clipped, then only the clipped rows count
+        for (mode, collided, clear) in [
+            (Mode::Chip8, 1, 0),
+            (Mode::Chip48, 4, 2),
+            (Mode::XoChip, 4, 2),
+        ] {
+            let mut machine = Chip8::new(mode.clone());
+            machine.memory[0x300..0x304].fill(0xFF);
+            machine.index_register = 0x300;
+            machine.registers[1] = 30;
+            machine.display[30][0] = 1;
+            machine.display[31][0] = 1;
+            run(&mut machine, 0xD014);
+            assert_eq!(machine.registers[0xF], collided, "{:?}", mode);
+
+            machine.vblank();
+            machine.display = [[0; 64]; 32];
+            run(&mut machine, 0xD014);
+            assert_eq!(machine.registers[0xF], clear, "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn sprite_reads_wrap_at_the_end_of_memory() {
+        for mode in [Mode::Chip8, Mode::XoChip] {
+            let mut machine = Chip8::new(mode.clone());
+            let end = machine.memory.len();
+            machine.memory[end - 1] = 0x80;
+            machine.memory[0] = 0x40;
+            machine.index_register = (end - 1) as u16;
+            run(&mut machine, 0xD012);
+            assert_eq!(lit_pixels(&machine), [(0, 0), (1, 1)], "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn draw_wraps_the_starting_position() {
+        let mut machine = Chip8::new(Mode::Chip48);
+        machine.memory[0x300] = 0x80;
+        machine.index_register = 0x300;
+        machine.registers[0] = 64 + 5;
+        machine.registers[1] = 32 + 7;
+        run(&mut machine, 0xD011);
+        assert_eq!(lit_pixels(&machine), [(5, 7)]);
+    }
+
+    #[test]
+    fn clipped_pixels_never_collide() {
+        let mut machine = Chip8::new(Mode::Chip48);
+        machine.memory[0x300] = 0xFF;
+        machine.index_register = 0x300;
+        machine.display[0][0] = 1;
+        machine.registers[0] = 60;
+        run(&mut machine, 0xD011);
+        assert_eq!(machine.registers[0xF], 0);
+        assert_eq!(machine.display[0][0], 1);
+    }
+
+    #[test]
+    fn display_bits_pack_and_unpack() {
+        let mut machine = Chip8::new(Mode::Chip8);
+        machine.display[0][0] = 1;
+        machine.display[0][63] = 1;
+        machine.display[31][1] = 1;
+        let bits = machine.display_bits();
+        assert_eq!(bits[0], 1 << 63 | 1);
+        assert_eq!(bits[31], 1 << 62);
+        assert_eq!(unpack_display(&bits), machine.display);
+    }
+
+    #[test]
+    fn reset_clears_the_program_but_keeps_the_font() {
+        let mut machine = Chip8::new(Mode::Chip8);
+        machine.load_font(&[0xF0; 5]).unwrap();
+        machine.load(&[0x6A, 0x2A, 0x22, 0x00]).unwrap();
+        machine.step(&KeyboardState::new()).unwrap();
+        machine.step(&KeyboardState::new()).unwrap();
+
+        machine.reset();
+        assert_eq!(machine.registers[0xA], 0);
+        assert!(machine.stack.is_empty());
+        assert_eq!(machine.program_counter, PROGRAM_START_ADDRESS);
+        assert_eq!(machine.memory[PROGRAM_START_ADDRESS], 0);
+        assert_eq!(machine.memory[FONTSET_START_ADDRESS..][..5], [0xF0; 5]);
+        assert!(machine.take_redraw());
+    }
+
+    #[test]
+    fn coverage_lists_each_kind_of_instruction_once() {
+        let mut machine = Chip8::new(Mode::Chip8);
+        assert_eq!(machine.coverage(), None);
+        machine.enable_coverage();
+        for opcode in [0x6A01, 0x6B02, 0x8AB4, 0xE123, 0x00E0] {
+            run(&mut machine, opcode);
+        }
+        assert_eq!(machine.coverage().unwrap(), ["00E0", "6XNN", "8XY4"]);
+    }
+
+    #[test]
+    fn templates_match_decoding() {
+        for opcode in 0..=u16::MAX {
+            if let Some(template) = decode(opcode).template() {
+                let matches = Opcode::TEMPLATES[template]
+                    .chars()
+                    .zip(format!("{:04X}", opcode).chars())
+                    .all(|(pattern, digit)| !pattern.is_ascii_hexdigit() || pattern == digit);
+                assert!(matches, "{:04X}", opcode);
+            }
+        }
+    }
+
+    #[test]
+    fn clones_run_identically_without_callbacks() {
+        use std::{cell::RefCell, rc::Rc};
+
+        let draws = Rc::new(RefCell::new(0));
+        let mut machine = Chip8::default();
+        let draw_count = draws.clone();
+        machine.set_on_draw(Box::new(move |_| *draw_count.borrow_mut() += 1));
+        machine.registers[0] = 3;
+
+        let mut snapshot = machine.clone();
+        run(&mut machine, 0xC0FF);
+        run(&mut snapshot, 0xC0FF);
+        assert_eq!(machine.registers, snapshot.registers);
+
+        run(&mut snapshot, 0x00E0);
+        assert_eq!(*draws.borrow(), 0);
+        run(&mut machine, 0x00E0);
+        assert_eq!(*draws.borrow(), 1);
+    }
+
+    #[test]
+    fn restoring_a_snapshot_keeps_the_callbacks() {
+        use std::{cell::RefCell, rc::Rc};
+
+        let sounds = Rc::new(RefCell::new(Vec::new()));
+        let mut machine = Chip8::default();
+        let sound_log = sounds.clone();
+        machine.set_on_sound(Box::new(move |on| sound_log.borrow_mut().push(on)));
+        let snapshot = machine.clone();
+
+        run(&mut machine, 0x6005);
+        run(&mut machine, 0xF018);
+        machine.restore(snapshot);
+        assert_eq!(machine.registers[0], 0);
+        run(&mut machine, 0x6005);
+        run(&mut machine, 0xF018);
+        assert_eq!(*sounds.borrow(), [true, false, true]);
+    }
+
+    #[test]
+    fn xo_chip_skips_over_the_whole_long_load() {
+        for (mode, skipped) in [(Mode::XoChip, 4), (Mode::Chip48, 2)] {
+            for opcode in [0x3000, 0x4001, 0x5010, 0x9020, 0xE0A1] {
+                let mut machine = Chip8::new(mode.clone());
+                machine.registers[2] = 1;
+                machine.load(&[0xF0, 0x00, 0x12, 0x34]).unwrap();
+                run(&mut machine, opcode);
+                assert_eq!(
+                    machine.program_counter,
+                    PROGRAM_START_ADDRESS + skipped,
+                    "{:04X}",
+                    opcode
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn a_long_load_at_the_end_of_memory_is_an_error() {
+        let mut machine = Chip8::new(Mode::XoChip);
+        machine.program_counter = XO_CHIP_MEMORY_SIZE;
+        assert!(matches!(
+            machine.execute(&decode(0xF000), &KeyboardState::new()),
+            Err(Chip8Error::AddressOutOfRange {
+                address: XO_CHIP_MEMORY_SIZE
+            })
+        ));
+    }
+
+    #[test]
+    fn memory_opcodes_wrap_at_the_top_of_xo_chip_memory() {
+        for start in [0xFFFE, 0xFFFF] {
+            let mut machine = Chip8::new(Mode::XoChip);
+            machine.index_register = start;
+            machine.registers[..3].copy_from_slice(&[1, 2, 3]);
+            run(&mut machine, 0xF255);
+            assert_eq!(machine.index_register, start.wrapping_add(3));
+            let written: Vec<u8> = (0..3)
+                .map(|i| machine.memory[(start as usize + i) % XO_CHIP_MEMORY_SIZE])
+                .collect();
+            assert_eq!(written, [1, 2, 3]);
+
+            machine.index_register = start;
+            machine.registers = [0; 16];
+            run(&mut machine, 0xF265);
+            assert_eq!(machine.registers[..3], [1, 2, 3]);
+
+            machine.index_register = start;
+            machine.registers[4] = 123;
+            run(&mut machine, 0xF433);
+            run(&mut machine, 0xF265);
+            assert_eq!(machine.registers[..3], [1, 2, 3]);
+
+            machine.index_register = start;
+            machine.registers[5] = 0xFF;
+            run(&mut machine, 0xF51E);
+            assert_eq!(machine.index_register, start.wrapping_add(0xFF));
+        }
+    }
+
+    #[test]
+    fn dump_state_lists_registers_and_stack() {
+        let mut machine = Chip8::new(Mode::Chip8);
+        machine.registers[0xA] = 0x2A;
+        run(&mut machine, 0x2300);
+        assert_eq!(
+            machine.dump_state(),
+            "PC: 0x0300\n\
+             I: 0x0000\n\
+             DT: 0x00\n\
+             ST: 0x00\n\
+             SP: 0x01\n\
+             Mode: Chip8\n\
+             V0-7: 00 00 00 00 00 00 00 00\n\
+             V8-F: 00 00 2a 00 00 00 00 00\n\
+             Stack: 0x0200"
+        );
+    }
+
+    #[test]
+    fn profiling_counts_opcode_groups_once_enabled() {
+        let mut machine = Chip8::new(Mode::Chip8);
+        run(&mut machine, 0x6000);
+        assert_eq!(machine.profile(), None);
+
+        machine.enable_profiling();
+        run(&mut machine, 0x6000);
+        run(&mut machine, 0x6101);
+        run(&mut machine, 0x00E0);
+        let profile = machine.profile().unwrap();
+        assert_eq!((profile[0x0], profile[0x6]), (1, 2));
+        assert_eq!(profile.iter().sum::<u64>(), 3);
+    }
+
+    #[test]
+    fn same_seed_produces_same_random_numbers() {
+        let mut first = Chip8::new(Mode::Chip8);
+        let mut second = Chip8::new(Mode::Chip8);
+        first.set_seed(42);
+        second.set_seed(42);
+
+        for _ in 0..32 {
+            // Set V0 = random byte AND 0xFF
+            run(&mut first, 0xC0FF);
+            run(&mut second, 0xC0FF);
+            assert_eq!(first.registers[0], second.registers[0]);
+        }
+    }
 }
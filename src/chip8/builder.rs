@@ -0,0 +1,77 @@
+use alloc::vec::Vec;
+
+use super::{Chip8, Chip8Error, Mode, Quirks, PROGRAM_START_ADDRESS};
+
+/// Sets up a machine in one expression, e.g.
+/// `Chip8::builder().mode(Mode::XoChip).seed(1).load(rom).build()`.
+#[derive(Clone, Debug)]
+pub struct Chip8Builder {
+    mode: Mode,
+    quirks: Option<Quirks>,
+    seed: Option<u64>,
+    load_address: usize,
+    strict: bool,
+    program: Vec<u8>,
+}
+
+impl Default for Chip8Builder {
+    fn default() -> Self {
+        Self {
+            mode: Mode::default(),
+            quirks: None,
+            seed: None,
+            load_address: PROGRAM_START_ADDRESS,
+            strict: false,
+            program: Vec::new(),
+        }
+    }
+}
+
+impl Chip8Builder {
+    pub fn mode(mut self, mode: Mode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Overrides the mode's usual quirks.
+    pub fn quirks(mut self, quirks: Quirks) -> Self {
+        self.quirks = Some(quirks);
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn load_address(mut self, address: usize) -> Self {
+        self.load_address = address;
+        self
+    }
+
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// The program to load at the load address.
+    pub fn load(mut self, program: &[u8]) -> Self {
+        self.program = program.to_vec();
+        self
+    }
+
+    /// The configured machine, or the error from setting the load address or loading.
+    pub fn build(self) -> Result<Chip8, Chip8Error> {
+        let mut machine = Chip8::new(self.mode);
+        if let Some(quirks) = self.quirks {
+            machine.quirks = quirks;
+        }
+        if let Some(seed) = self.seed {
+            machine.set_seed(seed);
+        }
+        machine.strict = self.strict;
+        machine.set_load_address(self.load_address)?;
+        machine.load(&self.program)?;
+        Ok(machine)
+    }
+}
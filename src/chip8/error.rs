@@ -0,0 +1,81 @@
+use core::fmt;
+
+/// Errors raised while running a program.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Chip8Error {
+    /// A subroutine was called at `address` with every stack slot already in use.
+    StackOverflow { address: usize },
+    /// A program of `size` bytes doesn't fit in the `available` bytes after its load address.
+    ProgramTooLarge { size: usize, available: usize },
+    /// `address` is outside of memory.
+    AddressOutOfRange { address: usize },
+    /// In strict mode, a `0000` opcode was executed at `address`, which usually
+    /// means the program ran into uninitialized memory.
+    Halt { address: usize },
+    /// A font of `size` bytes isn't whole 5 byte glyphs or is bigger than the 80 byte built-in font.
+    InvalidFont { size: usize },
+    /// In strict mode, the instruction at `address` moved the program counter to an odd `target`.
+    MisalignedJump { address: usize, target: usize },
+    /// In strict mode, an `opcode` that isn't part of the instruction set was executed at `address`.
+    UnknownOpcode { opcode: u16, address: usize },
+    /// With the interpreter protected, the instruction at `address` tried to write to `target`,
+    /// below the program area.
+    ProtectedWrite { address: usize, target: usize },
+    /// In strict mode, the instruction at `address` called machine code at `target`, which
+    /// can't be emulated without a handler for it.
+    MachineCall { target: usize, address: usize },
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Chip8Error::StackOverflow { address } => {
+                write!(f, "stack overflow calling subroutine at {:#06x}", address)
+            }
+            Chip8Error::ProgramTooLarge { size, available } => write!(
+                f,
+                "program is {} bytes but only {} bytes are available",
+                size, available
+            ),
+            Chip8Error::AddressOutOfRange { address } => {
+                write!(f, "address {:#06x} is outside of memory", address)
+            }
+            Chip8Error::Halt { address } => {
+                write!(f, "halted on opcode 0000 at {:#06x}", address)
+            }
+            Chip8Error::InvalidFont { size } => write!(
+                f,
+                "font is {} bytes but must be a multiple of 5 and at most 80",
+                size
+            ),
+            Chip8Error::MisalignedJump { address, target } => write!(
+                f,
+                "instruction at {:#06x} moved to odd address {:#06x}",
+                address, target
+            ),
+            Chip8Error::UnknownOpcode { opcode, address } => {
+                write!(f, "unknown opcode {:04X} at {:#06x}", opcode, address)
+            }
+            Chip8Error::ProtectedWrite { address, target } => write!(
+                f,
+                "instruction at {:#06x} wrote to the interpreter area at {:#06x}",
+                address, target
+            ),
+            Chip8Error::MachineCall { target, address } => write!(
+                f,
+                "instruction at {:#06x} called machine code at {:#05x}",
+                address, target
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Chip8Error {}
+
+#[cfg(feature = "std")]
+impl From<Chip8Error> for std::io::Error {
+    fn from(error: Chip8Error) -> Self {
+        std::io::Error::other(error)
+    }
+}
@@ -0,0 +1,316 @@
+use core::fmt;
+
+/// A decoded instruction. Register operands are indices into V0 to VF.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Opcode {
+    /// `0NNN`: call RCA 1802 machine code at NNN.
+    MachineCall(u16),
+    /// `00E0`
+    ClearScreen,
+    /// `00EE`
+    Return,
+    /// `1NNN`
+    Jump(u16),
+    /// `2NNN`
+    Call(u16),
+    /// `3XNN`: skip if Vx = NN.
+    SkipEqualByte(usize, u8),
+    /// `4XNN`: skip if Vx != NN.
+    SkipNotEqualByte(usize, u8),
+    /// `5XY0`: skip if Vx = Vy.
+    SkipEqual(usize, usize),
+    /// `6XNN`
+    LoadByte(usize, u8),
+    /// `7XNN`
+    AddByte(usize, u8),
+    /// `8XY0`
+    Move(usize, usize),
+    /// `8XY1`
+    Or(usize, usize),
+    /// `8XY2`
+    And(usize, usize),
+    /// `8XY3`
+    Xor(usize, usize),
+    /// `8XY4`
+    Add(usize, usize),
+    /// `8XY5`: Vx = Vx - Vy.
+    Sub(usize, usize),
+    /// `8XY6`
+    ShiftRight(usize, usize),
+    /// `8XY7`: Vx = Vy - Vx.
+    SubReverse(usize, usize),
+    /// `8XYE`
+    ShiftLeft(usize, usize),
+    /// `9XY0`: skip if Vx != Vy.
+    SkipNotEqual(usize, usize),
+    /// `ANNN`
+    LoadIndex(u16),
+    /// `BNNN`: jump to NNN plus V0, or Vx with the `jump_vx` quirk.
+    JumpOffset(usize, u16),
+    /// `CXNN`
+    Random(usize, u8),
+    /// `DXYN`
+    Draw(usize, usize, u8),
+    /// `EX9E`
+    SkipKeyPressed(usize),
+    /// `EXA1`
+    SkipKeyNotPressed(usize),
+    /// `FX07`
+    LoadDelay(usize),
+    /// `FX0A`
+    WaitKey(usize),
+    /// `FX15`
+    SetDelay(usize),
+    /// `FX18`
+    SetSound(usize),
+    /// `FX1E`
+    AddIndex(usize),
+    /// `FX29`
+    LoadFont(usize),
+    /// `FX33`
+    StoreBcd(usize),
+    /// `FX55`
+    StoreRegisters(usize),
+    /// `FX65`
+    LoadRegisters(usize),
+    /// `FX75`
+    StoreFlags(usize),
+    /// `FX85`
+    LoadFlags(usize),
+    /// `F000 NNNN`: XO-CHIP's I = the 16-bit address in the following word.
+    LoadLongIndex,
+    /// `F002`: XO-CHIP's audio pattern = the 16 bytes at I.
+    LoadAudio,
+    /// `FX3A`: XO-CHIP's pitch = Vx.
+    SetPitch(usize),
+    /// Anything else.
+    Unknown(u16),
+}
+
+impl Opcode {
+    /// Every instruction's pattern, in the order of the variants.
+    pub const TEMPLATES: [&'static str; 40] = [
+        "0NNN", "00E0", "00EE", "1NNN", "2NNN", "3XNN", "4XNN", "5XY0", "6XNN", "7XNN", "8XY0",
+        "8XY1", "8XY2", "8XY3", "8XY4", "8XY5", "8XY6", "8XY7", "8XYE", "9XY0", "ANNN", "BNNN",
+        "CXNN", "DXYN", "EX9E", "EXA1", "FX07", "FX0A", "FX15", "FX18", "FX1E", "FX29", "FX33",
+        "FX55", "FX65", "FX75", "FX85", "F000", "F002", "FX3A",
+    ];
+
+    /// The index of this instruction's pattern in `TEMPLATES`, or `None` when unknown.
+    pub fn template(&self) -> Option<usize> {
+        let index = match self {
+            Opcode::MachineCall(_) => 0,
+            Opcode::ClearScreen => 1,
+            Opcode::Return => 2,
+            Opcode::Jump(_) => 3,
+            Opcode::Call(_) => 4,
+            Opcode::SkipEqualByte(..) => 5,
+            Opcode::SkipNotEqualByte(..) => 6,
+            Opcode::SkipEqual(..) => 7,
+            Opcode::LoadByte(..) => 8,
+            Opcode::AddByte(..) => 9,
+            Opcode::Move(..) => 10,
+            Opcode::Or(..) => 11,
+            Opcode::And(..) => 12,
+            Opcode::Xor(..) => 13,
+            Opcode::Add(..) => 14,
+            Opcode::Sub(..) => 15,
+            Opcode::ShiftRight(..) => 16,
+            Opcode::SubReverse(..) => 17,
+            Opcode::ShiftLeft(..) => 18,
+            Opcode::SkipNotEqual(..) => 19,
+            Opcode::LoadIndex(_) => 20,
+            Opcode::JumpOffset(..) => 21,
+            Opcode::Random(..) => 22,
+            Opcode::Draw(..) => 23,
+            Opcode::SkipKeyPressed(_) => 24,
+            Opcode::SkipKeyNotPressed(_) => 25,
+            Opcode::LoadDelay(_) => 26,
+            Opcode::WaitKey(_) => 27,
+            Opcode::SetDelay(_) => 28,
+            Opcode::SetSound(_) => 29,
+            Opcode::AddIndex(_) => 30,
+            Opcode::LoadFont(_) => 31,
+            Opcode::StoreBcd(_) => 32,
+            Opcode::StoreRegisters(_) => 33,
+            Opcode::LoadRegisters(_) => 34,
+            Opcode::StoreFlags(_) => 35,
+            Opcode::LoadFlags(_) => 36,
+            Opcode::LoadLongIndex => 37,
+            Opcode::LoadAudio => 38,
+            Opcode::SetPitch(_) => 39,
+            Opcode::Unknown(_) => return None,
+        };
+
+        Some(index)
+    }
+
+    /// The 16-bit opcode this decodes from.
+    pub fn encode(&self) -> u16 {
+        let xy = |x: usize, y: usize| (x as u16) << 8 | (y as u16) << 4;
+        let xnn = |x: usize, nn: u8| (x as u16) << 8 | nn as u16;
+        let x = |x: usize| (x as u16) << 8;
+        match *self {
+            Opcode::MachineCall(nnn) => nnn,
+            Opcode::ClearScreen => 0x00E0,
+            Opcode::Return => 0x00EE,
+            Opcode::Jump(nnn) => 0x1000 | nnn,
+            Opcode::Call(nnn) => 0x2000 | nnn,
+            Opcode::SkipEqualByte(vx, nn) => 0x3000 | xnn(vx, nn),
+            Opcode::SkipNotEqualByte(vx, nn) => 0x4000 | xnn(vx, nn),
+            Opcode::SkipEqual(vx, vy) => 0x5000 | xy(vx, vy),
+            Opcode::LoadByte(vx, nn) => 0x6000 | xnn(vx, nn),
+            Opcode::AddByte(vx, nn) => 0x7000 | xnn(vx, nn),
+            Opcode::Move(vx, vy) => 0x8000 | xy(vx, vy),
+            Opcode::Or(vx, vy) => 0x8001 | xy(vx, vy),
+            Opcode::And(vx, vy) => 0x8002 | xy(vx, vy),
+            Opcode::Xor(vx, vy) => 0x8003 | xy(vx, vy),
+            Opcode::Add(vx, vy) => 0x8004 | xy(vx, vy),
+            Opcode::Sub(vx, vy) => 0x8005 | xy(vx, vy),
+            Opcode::ShiftRight(vx, vy) => 0x8006 | xy(vx, vy),
+            Opcode::SubReverse(vx, vy) => 0x8007 | xy(vx, vy),
+            Opcode::ShiftLeft(vx, vy) => 0x800E | xy(vx, vy),
+            Opcode::SkipNotEqual(vx, vy) => 0x9000 | xy(vx, vy),
+            Opcode::LoadIndex(nnn) => 0xA000 | nnn,
+            Opcode::JumpOffset(_, nnn) => 0xB000 | nnn,
+            Opcode::Random(vx, nn) => 0xC000 | xnn(vx, nn),
+            Opcode::Draw(vx, vy, n) => 0xD000 | xy(vx, vy) | n as u16,
+            Opcode::SkipKeyPressed(vx) => 0xE09E | x(vx),
+            Opcode::SkipKeyNotPressed(vx) => 0xE0A1 | x(vx),
+            Opcode::LoadDelay(vx) => 0xF007 | x(vx),
+            Opcode::WaitKey(vx) => 0xF00A | x(vx),
+            Opcode::SetDelay(vx) => 0xF015 | x(vx),
+            Opcode::SetSound(vx) => 0xF018 | x(vx),
+            Opcode::AddIndex(vx) => 0xF01E | x(vx),
+            Opcode::LoadFont(vx) => 0xF029 | x(vx),
+            Opcode::StoreBcd(vx) => 0xF033 | x(vx),
+            Opcode::StoreRegisters(vx) => 0xF055 | x(vx),
+            Opcode::LoadRegisters(vx) => 0xF065 | x(vx),
+            Opcode::StoreFlags(vx) => 0xF075 | x(vx),
+            Opcode::LoadFlags(vx) => 0xF085 | x(vx),
+            Opcode::LoadLongIndex => 0xF000,
+            Opcode::LoadAudio => 0xF002,
+            Opcode::SetPitch(vx) => 0xF03A | x(vx),
+            Opcode::Unknown(opcode) => opcode,
+        }
+    }
+
+    /// Whether this only exists in XO-CHIP.
+    pub fn is_xo_chip(&self) -> bool {
+        matches!(
+            self,
+            Opcode::LoadLongIndex | Opcode::LoadAudio | Opcode::SetPitch(_)
+        )
+    }
+}
+
+pub fn decode(opcode: u16) -> Opcode {
+    let x = ((opcode & 0x0F00) >> 8) as usize;
+    let y = ((opcode & 0x00F0) >> 4) as usize;
+    let n = (opcode & 0x000F) as u8;
+    let nn = (opcode & 0x00FF) as u8;
+    let nnn = opcode & 0x0FFF;
+    match opcode >> 12 {
+        0x0 => match nnn {
+            0x0E0 => Opcode::ClearScreen,
+            0x0EE => Opcode::Return,
+            _ => Opcode::MachineCall(nnn),
+        },
+        0x1 => Opcode::Jump(nnn),
+        0x2 => Opcode::Call(nnn),
+        0x3 => Opcode::SkipEqualByte(x, nn),
+        0x4 => Opcode::SkipNotEqualByte(x, nn),
+        0x5 if n == 0 => Opcode::SkipEqual(x, y),
+        0x6 => Opcode::LoadByte(x, nn),
+        0x7 => Opcode::AddByte(x, nn),
+        0x8 => match n {
+            0x0 => Opcode::Move(x, y),
+            0x1 => Opcode::Or(x, y),
+            0x2 => Opcode::And(x, y),
+            0x3 => Opcode::Xor(x, y),
+            0x4 => Opcode::Add(x, y),
+            0x5 => Opcode::Sub(x, y),
+            0x6 => Opcode::ShiftRight(x, y),
+            0x7 => Opcode::SubReverse(x, y),
+            0xE => Opcode::ShiftLeft(x, y),
+            _ => Opcode::Unknown(opcode),
+        },
+        0x9 if n == 0 => Opcode::SkipNotEqual(x, y),
+        0xA => Opcode::LoadIndex(nnn),
+        0xB => Opcode::JumpOffset(x, nnn),
+        0xC => Opcode::Random(x, nn),
+        0xD => Opcode::Draw(x, y, n),
+        0xE => match nn {
+            0x9E => Opcode::SkipKeyPressed(x),
+            0xA1 => Opcode::SkipKeyNotPressed(x),
+            _ => Opcode::Unknown(opcode),
+        },
+        0xF => match nn {
+            0x00 if x == 0 => Opcode::LoadLongIndex,
+            0x02 if x == 0 => Opcode::LoadAudio,
+            0x07 => Opcode::LoadDelay(x),
+            0x0A => Opcode::WaitKey(x),
+            0x15 => Opcode::SetDelay(x),
+            0x18 => Opcode::SetSound(x),
+            0x1E => Opcode::AddIndex(x),
+            0x29 => Opcode::LoadFont(x),
+            0x33 => Opcode::StoreBcd(x),
+            0x3A => Opcode::SetPitch(x),
+            0x55 => Opcode::StoreRegisters(x),
+            0x65 => Opcode::LoadRegisters(x),
+            0x75 => Opcode::StoreFlags(x),
+            0x85 => Opcode::LoadFlags(x),
+            _ => Opcode::Unknown(opcode),
+        },
+        _ => Opcode::Unknown(opcode),
+    }
+}
+
+/// Formats as the assembly mnemonic understood by `asm::assemble`.
+impl fmt::Display for Opcode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Opcode::MachineCall(nnn) => write!(f, "SYS {:#05X}", nnn),
+            Opcode::ClearScreen => write!(f, "CLS"),
+            Opcode::Return => write!(f, "RET"),
+            Opcode::Jump(nnn) => write!(f, "JP {:#05X}", nnn),
+            Opcode::Call(nnn) => write!(f, "CALL {:#05X}", nnn),
+            Opcode::SkipEqualByte(x, nn) => write!(f, "SE V{:X}, {:#04X}", x, nn),
+            Opcode::SkipNotEqualByte(x, nn) => write!(f, "SNE V{:X}, {:#04X}", x, nn),
+            Opcode::SkipEqual(x, y) => write!(f, "SE V{:X}, V{:X}", x, y),
+            Opcode::LoadByte(x, nn) => write!(f, "LD V{:X}, {:#04X}", x, nn),
+            Opcode::AddByte(x, nn) => write!(f, "ADD V{:X}, {:#04X}", x, nn),
+            Opcode::Move(x, y) => write!(f, "LD V{:X}, V{:X}", x, y),
+            Opcode::Or(x, y) => write!(f, "OR V{:X}, V{:X}", x, y),
+            Opcode::And(x, y) => write!(f, "AND V{:X}, V{:X}", x, y),
+            Opcode::Xor(x, y) => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Opcode::Add(x, y) => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Opcode::Sub(x, y) => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Opcode::ShiftRight(x, y) => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Opcode::SubReverse(x, y) => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Opcode::ShiftLeft(x, y) => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Opcode::SkipNotEqual(x, y) => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Opcode::LoadIndex(nnn) => write!(f, "LD I, {:#05X}", nnn),
+            Opcode::JumpOffset(_, nnn) => write!(f, "JP V0, {:#05X}", nnn),
+            Opcode::Random(x, nn) => write!(f, "RND V{:X}, {:#04X}", x, nn),
+            Opcode::Draw(x, y, n) => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Opcode::SkipKeyPressed(x) => write!(f, "SKP V{:X}", x),
+            Opcode::SkipKeyNotPressed(x) => write!(f, "SKNP V{:X}", x),
+            Opcode::LoadDelay(x) => write!(f, "LD V{:X}, DT", x),
+            Opcode::WaitKey(x) => write!(f, "LD V{:X}, K", x),
+            Opcode::SetDelay(x) => write!(f, "LD DT, V{:X}", x),
+            Opcode::SetSound(x) => write!(f, "LD ST, V{:X}", x),
+            Opcode::AddIndex(x) => write!(f, "ADD I, V{:X}", x),
+            Opcode::LoadFont(x) => write!(f, "LD F, V{:X}", x),
+            Opcode::StoreBcd(x) => write!(f, "LD B, V{:X}", x),
+            Opcode::StoreRegisters(x) => write!(f, "LD [I], V{:X}", x),
+            Opcode::LoadRegisters(x) => write!(f, "LD V{:X}, [I]", x),
+            Opcode::StoreFlags(x) => write!(f, "LD R, V{:X}", x),
+            Opcode::LoadFlags(x) => write!(f, "LD V{:X}, R", x),
+            Opcode::LoadLongIndex => write!(f, "LD I, LONG"),
+            Opcode::LoadAudio => write!(f, "AUDIO"),
+            Opcode::SetPitch(x) => write!(f, "PITCH V{:X}", x),
+            Opcode::Unknown(opcode) => write!(f, "DW {:#06X}", opcode),
+        }
+    }
+}
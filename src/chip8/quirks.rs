@@ -0,0 +1,100 @@
+use super::Mode;
+
+/// Behaviors that differ between CHIP-8 interpreters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Quirks {
+    /// `8XY1`, `8XY2` and `8XY3` reset VF to 0.
+    pub vf_reset: bool,
+    /// `FX55` and `FX65` leave I pointing past the last register accessed.
+    pub memory_increment: bool,
+    /// `8XY6` and `8XYE` shift Vy into Vx rather than shifting Vx in place.
+    pub shift_vy: bool,
+    /// `BNNN` jumps to NNN + Vx, with x taken from the address, rather than NNN + V0.
+    pub jump_vx: bool,
+    /// `DXYN` waits for the vertical blank, so only one sprite is drawn per frame.
+    pub display_wait: bool,
+    /// `DXYN` uses up one extra instruction of the frame's budget per sprite row, so frames that
+    /// draw run fewer instructions. The COSMAC VIP spent roughly an instruction's worth of time
+    /// on each row it drew, on top of the vertical blank wait. Off on every platform by default.
+    pub draw_cost: bool,
+    /// `DXYN` sets VF to the number of sprite rows that collided or were clipped at the bottom
+    /// edge, as SUPER-CHIP does, rather than just 1 for any collision.
+    pub collision_rows: bool,
+}
+
+impl From<&Mode> for Quirks {
+    fn from(mode: &Mode) -> Self {
+        match mode {
+            Mode::Chip8 => Self {
+                vf_reset: true,
+                memory_increment: true,
+                shift_vy: true,
+                jump_vx: false,
+                display_wait: true,
+                draw_cost: false,
+                collision_rows: false,
+            },
+            Mode::Chip48 => Self {
+                vf_reset: false,
+                memory_increment: false,
+                shift_vy: false,
+                jump_vx: true,
+                display_wait: false,
+                draw_cost: false,
+                collision_rows: true,
+            },
+            Mode::XoChip => Self {
+                vf_reset: false,
+                memory_increment: true,
+                shift_vy: true,
+                jump_vx: false,
+                display_wait: false,
+                draw_cost: false,
+                collision_rows: true,
+            },
+        }
+    }
+}
+
+/// Named platforms from the community's quirk tables, each a mode and the quirks its
+/// interpreter is documented to have.
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Platform {
+    /// The original interpreter on the COSMAC VIP.
+    Vip,
+    /// SUPER-CHIP as modern interpreters run it, without waiting for the vertical blank.
+    SchipModern,
+    /// SUPER-CHIP 1.1 on the HP 48, which waits for the vertical blank to draw.
+    SchipLegacy,
+    XoChip,
+    /// Octo's CHIP-8 defaults: XO-CHIP's quirks on the original instruction set, except that
+    /// `DXYN` sets VF to 1 for any collision rather than counting rows, as CHIP-8 programs expect.
+    Octo,
+}
+
+impl Platform {
+    pub fn mode(&self) -> Mode {
+        match self {
+            Platform::Vip | Platform::Octo => Mode::Chip8,
+            Platform::SchipModern | Platform::SchipLegacy => Mode::Chip48,
+            Platform::XoChip => Mode::XoChip,
+        }
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        match self {
+            Platform::Vip => Quirks::from(&Mode::Chip8),
+            Platform::SchipModern => Quirks::from(&Mode::Chip48),
+            Platform::SchipLegacy => Quirks {
+                display_wait: true,
+                ..Quirks::from(&Mode::Chip48)
+            },
+            Platform::XoChip => Quirks::from(&Mode::XoChip),
+            Platform::Octo => Quirks {
+                collision_rows: false,
+                ..Quirks::from(&Mode::XoChip)
+            },
+        }
+    }
+}
@@ -1,24 +1,523 @@
-use clap::{command, Parser};
+use std::{
+    ffi::OsString,
+    io::{Error, ErrorKind},
+    path::PathBuf,
+};
 
-use crate::chip8::Mode;
+use clap::Parser;
+use crossterm::style::Color;
+
+use chip8::{Mode, Platform};
+
+use crate::{
+    config::{self, Config, QuirkSettings},
+    keymap::{parse_keymap, Keymap, Layout, QuitKey},
+    render::RenderMode,
+};
 
 /// chip8 emulator
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, args_override_self = true)]
 pub struct CliOptions {
-    /// The program to run.
+    /// Read default options from this TOML file instead of ~/.config/chip8/config.toml. Keys are
+    /// the long option names in snake case, quirks go in a [quirks] table.
+    #[arg(long)]
+    pub config: Option<String>,
+
+    /// Quirks from the config file.
+    #[arg(skip)]
+    pub quirks: QuirkSettings,
+
+    /// The programs to run, paths or http(s) URLs when built with the http feature. With more
+    /// than one, n moves on to the next.
+    #[arg(short, long, num_args = 1.., required_unless_present_any = ["assemble", "connect", "selftest"])]
+    pub program: Vec<String>,
+
+    /// The file to run from a .zip program, needed when it holds more than one. Requires the
+    /// zip feature.
+    #[arg(long)]
+    pub entry: Option<String>,
+
+    /// Assemble this source file into a ROM instead of running a program.
+    #[arg(long, requires = "output")]
+    pub assemble: Option<String>,
+
+    /// Where to write the ROM built by --assemble.
+    #[arg(short, long)]
+    pub output: Option<String>,
+
+    /// The mode to run in, chip48 unless the ROM is recognized [default: chip48]
+    #[arg(short, long)]
+    pub mode: Option<Mode>,
+
+    /// Run as this platform, with its mode, memory size and quirks, instead of --mode or what
+    /// the ROM database says. The [quirks] table still applies on top.
+    #[arg(long, conflicts_with = "mode")]
+    pub platform: Option<Platform>,
+
+    /// Look ROMs up in this copy of the community CHIP-8 database's programs.json
+    /// instead of the built in one.
+    #[arg(long)]
+    pub rom_db: Option<String>,
+
+    /// Operations to run per second. Defaults to what the platform's games expect: 660 for
+    /// CHIP-8, 1200 for the octo platform, 1800 for SUPER-CHIP and 60000 for XO-CHIP.
     #[arg(short, long)]
-    pub program: String,
+    pub speed: Option<u64>,
 
-    /// The mode to run in.
-    #[arg(short, long, default_value = "chip48")]
-    pub mode: Mode,
+    /// Most times per second to redraw the terminal, for slow terminals and SSH. The timers
+    /// still run at 60hz.
+    #[arg(long, default_value_t = 60, value_parser = clap::value_parser!(u64).range(1..=60))]
+    pub fps: u64,
 
-    /// Operations to run per second.
-    #[arg(short, long, default_value = "700")]
-    pub speed: u64,
+    /// Run frames back to back instead of at 60hz, with the timers ticking every speed/60
+    /// instructions, so a ROM, --seed and --replay always produce the same output.
+    #[arg(long)]
+    pub fixed_timestep: bool,
+
+    /// Speeds F1, F2 and so on jump to, as whole multiples or fractions like 0.5 or 1/4.
+    #[arg(long, default_value = "1,2,4,0.5", value_delimiter = ',', value_parser = parse_speed_preset)]
+    pub speed_presets: Vec<SpeedPreset>,
 
     // Flag for printing debug information.
     #[arg(short, long)]
     pub debug: bool,
+
+    /// Log to stderr at this level: off, error, warn, info, debug or trace. Redirect stderr to
+    /// a file to keep the log off the display.
+    #[arg(long, default_value = "off")]
+    pub log_level: log::LevelFilter,
+
+    /// Show a note beside the display while the sound timer is running, for when there's no
+    /// audio.
+    #[arg(long)]
+    pub visual_beep: bool,
+
+    /// Keep beeps on for at least this long, since a sound timer of 1 lasts a single 60hz frame
+    /// and can be too short to notice. The default of 0 keeps beeps exactly as long as the
+    /// sound timer.
+    #[arg(long, default_value_t = 0)]
+    pub min_beep_ms: u64,
+
+    /// How to draw the display in the terminal.
+    #[arg(long, value_enum, default_value_t)]
+    pub render_mode: RenderMode,
+
+    /// Character drawn for pixels that are on.
+    #[arg(long, default_value = "█")]
+    pub pixel_on: char,
+
+    /// Character drawn for pixels that are off.
+    #[arg(long, default_value = " ")]
+    pub pixel_off: char,
+
+    /// Keypad layout, as 16 keys for values 0-F or a path to a file containing them.
+    #[arg(long, default_value = crate::keymap::DEFAULT_KEYMAP, value_parser = parse_keymap)]
+    pub keymap: Keymap,
+
+    /// Extra keys on top of the keymap. Modern binds the arrows to 2, 4, 6 and 8, space to 5
+    /// and enter to F, which is what most games use, so the hex keypad isn't needed.
+    #[arg(long, value_enum, default_value_t)]
+    pub layout: Layout,
+
+    /// The key that quits: a character, esc, or ctrl- and a character. It can't also be in the
+    /// keymap.
+    #[arg(long, default_value = "ctrl-c")]
+    pub quit_key: QuitKey,
+
+    /// Reset and reload a program whenever its file changes, e.g. after reassembling it.
+    #[cfg(feature = "watch")]
+    #[arg(long)]
+    pub watch_rom: bool,
+
+    /// Read input from connected gamepads as well as the keyboard.
+    #[cfg(feature = "gamepad")]
+    #[arg(long)]
+    pub gamepad: bool,
+
+    /// Gamepad buttons bound to keypad values, as Button=key pairs.
+    #[cfg(feature = "gamepad")]
+    #[arg(
+        long,
+        default_value = crate::gamepad::DEFAULT_GAMEPAD_MAP,
+        value_parser = crate::gamepad::parse_gamepad_map
+    )]
+    pub gamepad_map: crate::gamepad::GamepadMap,
+
+    /// Override whether draws wait for the vertical blank, limiting them to one per frame.
+    #[arg(long)]
+    pub display_wait: Option<bool>,
+
+    /// Override whether each draw uses up an extra instruction of the frame per sprite row, for
+    /// the COSMAC VIP's timing.
+    #[arg(long)]
+    pub draw_cost: Option<bool>,
+
+    /// Stop with an error on suspicious programs, such as running into zeroed memory, unknown opcodes or jumps to odd addresses.
+    #[arg(long)]
+    pub strict: bool,
+
+    /// Stop with an error when the program writes below 0x200, over the interpreter and font.
+    #[arg(long)]
+    pub protect_interpreter: bool,
+
+    /// Warn under the display whenever the program counter ends up on an odd address.
+    #[arg(long)]
+    pub warn_misaligned: bool,
+
+    /// Warn under the display the first time each unknown opcode is skipped, with its address.
+    /// Unknown opcodes are otherwise skipped quietly, or errors with --strict.
+    #[arg(long)]
+    pub trace_unknown: bool,
+
+    /// How deep subroutine calls can nest before the stack overflows.
+    #[arg(long, default_value_t = chip8::DEFAULT_STACK_DEPTH)]
+    pub stack_depth: usize,
+
+    /// Address programs are loaded at and start from, e.g. 0x600 for ETI-660 programs.
+    #[arg(long, default_value = "0x200", value_parser = parse_number)]
+    pub load_address: usize,
+
+    /// Set a register before running, e.g. V3=0x2A. Can be repeated.
+    #[arg(long, value_name = "Vx=NN", value_parser = parse_register_assignment)]
+    pub set_reg: Vec<(usize, u8)>,
+
+    /// Set a byte of memory after loading the program, e.g. 0x300=0xFF. Can be repeated.
+    #[arg(long, value_name = "ADDR=BB", value_parser = parse_memory_assignment)]
+    pub set_mem: Vec<(usize, u8)>,
+
+    /// Replace the built-in font with this file, 5 bytes per glyph for up to 16 glyphs.
+    #[arg(long)]
+    pub font: Option<String>,
+
+    /// File the SCHIP RPL user flags are loaded from and saved to, so they persist between runs.
+    #[arg(long)]
+    pub rpl_file: Option<String>,
+
+    /// Write all of memory to this file on exit, including when the program stops with an error.
+    #[arg(long)]
+    pub dump_memory: Option<String>,
+
+    /// Run headless, serving the display and taking keys from a --connect client on this
+    /// address, e.g. 0.0.0.0:8008.
+    #[arg(long)]
+    pub serve: Option<String>,
+
+    /// Play a program being served by --serve at this address instead of running one.
+    #[arg(long, conflicts_with = "serve")]
+    pub connect: Option<String>,
+
+    /// Count the instructions executed by opcode group and print them on exit.
+    #[arg(long)]
+    pub profile: bool,
+
+    /// List which kinds of instruction ran, and which never did, on exit.
+    #[arg(long)]
+    pub coverage: bool,
+
+    /// Check that each quirk behaves as configured for the mode, printing PASS or FAIL for each.
+    #[arg(long)]
+    pub selftest: bool,
+
+    /// Print the program's size, hash, matching profile and opcode mix instead of running it.
+    #[arg(long)]
+    pub info: bool,
+
+    /// Print the program's disassembly instead of running it.
+    #[arg(long)]
+    pub disassemble: bool,
+
+    /// Check that every word of the program decodes to an instruction for the mode, without
+    /// running it. Exits with an error listing any that don't.
+    #[arg(long)]
+    pub validate: bool,
+
+    /// Start in a monitor on the terminal to examine, change and step the machine instead of
+    /// running it.
+    #[arg(long)]
+    pub monitor: bool,
+
+    /// Fade pixels out over a few frames like a CRT's phosphor, to hide sprite flicker.
+    #[arg(long)]
+    pub ghosting: bool,
+
+    /// Mirror the terminal display left to right.
+    #[arg(long)]
+    pub flip_h: bool,
+
+    /// Mirror the terminal display top to bottom.
+    #[arg(long)]
+    pub flip_v: bool,
+
+    /// Save the display as a PBM image to this file on exit.
+    #[arg(long)]
+    pub screenshot: Option<String>,
+
+    /// Record the rendered frames to an animated GIF, written on exit.
+    #[arg(long)]
+    pub record_gif: Option<String>,
+
+    /// The most frames to keep for --record-gif, later frames are dropped.
+    #[arg(long, default_value_t = 3600)]
+    pub max_gif_frames: usize,
+
+    /// Quit after running for this many seconds of wall clock time, e.g. for fixed length
+    /// --record-gif captures.
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub duration: Option<u64>,
+
+    /// How many frames ',' can step back through. Each costs a copy of the machine, 0
+    /// disables rewinding.
+    #[arg(long, default_value_t = 600)]
+    pub rewind_depth: usize,
+
+    /// Upscale image output, including --render-mode kitty, by this whole factor.
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..=64))]
+    pub scale: u64,
+
+    /// Run this many instructions as fast as possible without a display, then report the speed.
+    #[arg(long)]
+    pub bench: Option<u64>,
+
+    /// Run this many instructions without a display, at --speed's frame rate and with no keys
+    /// pressed, then print the SHA-1 of the final display.
+    #[arg(long)]
+    pub max_cycles: Option<u64>,
+
+    /// With --max-cycles, exit with an error unless the final display has this SHA-1, for
+    /// checking ROMs in CI.
+    #[arg(long, requires = "max_cycles")]
+    pub expect_hash: Option<String>,
+
+    /// With --max-cycles, print the final registers, timers, stack and packed display as JSON
+    /// instead of just the display's SHA-1.
+    #[arg(long, requires = "max_cycles")]
+    pub json: bool,
+
+    /// Seed for the random number generator, random if not given.
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// On terminals that don't report key releases, how long a key stays down after its last
+    /// press or repeat. Raise it if held keys flicker, lower it if they feel sticky.
+    #[arg(long, default_value_t = 150)]
+    pub key_hold_ms: u64,
+
+    /// Record keypad input to a file for later replay.
+    #[arg(long, conflicts_with = "replay")]
+    pub record: Option<String>,
+
+    /// Replay keypad input from a file recorded with --record instead of reading the keyboard.
+    #[arg(long)]
+    pub replay: Option<String>,
+
+    /// Color for pixels that are on (a named color or #RRGGBB).
+    #[arg(long, default_value = "reset", value_parser = parse_color)]
+    pub fg_color: Color,
+
+    /// Color behind the display (a named color or #RRGGBB).
+    #[arg(long, default_value = "reset", value_parser = parse_color)]
+    pub bg_color: Color,
+
+    /// Color for pixels only on in the second plane (XO-CHIP).
+    #[arg(long, default_value = "dark_grey", value_parser = parse_color)]
+    pub plane2_color: Color,
+
+    /// Color for pixels on in both planes (XO-CHIP).
+    #[arg(long, default_value = "grey", value_parser = parse_color)]
+    pub blend_color: Color,
+}
+
+impl CliOptions {
+    /// Parses the command line on top of the config file's options.
+    pub fn load() -> Result<Self, Error> {
+        let mut args: Vec<OsString> = std::env::args_os().collect();
+        let config = match config_flag(&args) {
+            Some(path) => Some(Config::open(&path)?),
+            None => match config::default_path() {
+                Some(path) if path.exists() => Some(Config::open(&path)?),
+                _ => None,
+            },
+        };
+
+        let mut options = match config {
+            Some(config) => {
+                // Flags given later override earlier ones, so the command line wins
+                args.splice(1..1, config.args);
+                let mut options = Self::parse_from(args);
+                options.quirks = config.quirks;
+                options
+            }
+            None => Self::parse_from(args),
+        };
+
+        options.keymap.add_layout(options.layout);
+        options
+            .keymap
+            .set_quit_key(options.quit_key)
+            .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+        Ok(options)
+    }
+
+    /// --speed, or the usual speed for the platform or the mode being run.
+    pub fn speed(&self, mode: &Mode) -> u64 {
+        self.speed.unwrap_or(match (self.platform, mode) {
+            (Some(Platform::Octo), _) => 1200,
+            (_, Mode::Chip8) => 660,
+            (_, Mode::Chip48) => 1800,
+            (_, Mode::XoChip) => 60000,
+        })
+    }
+
+    /// The horizontal and vertical flips for the terminal display.
+    pub fn flip(&self) -> (bool, bool) {
+        (self.flip_h, self.flip_v)
+    }
+
+    /// Colors indexed by a pixel's plane bits: off, plane 1, plane 2, both.
+    pub fn palette(&self) -> [Color; 4] {
+        [
+            self.bg_color,
+            self.fg_color,
+            self.plane2_color,
+            self.blend_color,
+        ]
+    }
+}
+
+/// Finds --config before clap runs, since the config file supplies arguments clap needs.
+fn config_flag(args: &[OsString]) -> Option<PathBuf> {
+    let mut args = args.iter().skip(1).map(|arg| arg.to_string_lossy());
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        } else if arg == "--config" {
+            return args.next().map(|path| PathBuf::from(path.as_ref()));
+        } else if let Some(path) = arg.strip_prefix("--config=") {
+            return Some(PathBuf::from(path));
+        }
+    }
+
+    None
+}
+
+pub fn parse_number(value: &str) -> Result<usize, String> {
+    match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => value.parse(),
+    }
+    .map_err(|e| format!("invalid number '{}': {}", value, e))
+}
+
+/// A runtime speed, as frames emulated per frame or frames waited per emulated frame.
+#[derive(Clone, Copy, Debug)]
+pub struct SpeedPreset {
+    pub speedup: u64,
+    pub slowdown: u64,
+}
+
+fn parse_speed_preset(value: &str) -> Result<SpeedPreset, String> {
+    let speed = match value.split_once('/') {
+        Some((numerator, denominator)) => numerator
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .zip(denominator.trim().parse::<f64>().ok())
+            .map(|(numerator, denominator)| numerator / denominator),
+        None => value.trim().parse::<f64>().ok(),
+    }
+    .filter(|speed| speed.is_finite() && *speed > 0.0)
+    .ok_or_else(|| format!("invalid speed '{}'", value))?;
+
+    // Slower speeds are held as how many frames to wait, so they need whole reciprocals
+    let (speedup, slowdown) = if speed >= 1.0 {
+        (speed, 1.0)
+    } else {
+        (1.0, 1.0 / speed)
+    };
+    let whole =
+        |n: f64| (n.fract().abs() < 1e-9 && n <= crate::MAX_SLOWDOWN as f64).then_some(n as u64);
+    match (whole(speedup), whole(slowdown)) {
+        (Some(speedup), Some(slowdown)) => Ok(SpeedPreset { speedup, slowdown }),
+        _ => Err(format!(
+            "speed '{}' isn't a whole multiple or fraction up to {}",
+            value,
+            crate::MAX_SLOWDOWN
+        )),
+    }
+}
+
+fn parse_byte(value: &str) -> Result<u8, String> {
+    let byte = parse_number(value)?;
+    u8::try_from(byte).map_err(|_| format!("{} doesn't fit in a byte", value))
+}
+
+fn parse_register_assignment(value: &str) -> Result<(usize, u8), String> {
+    let (register, byte) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected Vx=NN, got '{}'", value))?;
+    let index = register
+        .strip_prefix(['V', 'v'])
+        .filter(|index| index.len() == 1)
+        .and_then(|index| usize::from_str_radix(index, 16).ok())
+        .ok_or_else(|| format!("expected a register V0 to VF, got '{}'", register))?;
+
+    Ok((index, parse_byte(byte)?))
+}
+
+pub fn parse_memory_assignment(value: &str) -> Result<(usize, u8), String> {
+    let (address, byte) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected ADDR=BB, got '{}'", value))?;
+    let address = parse_number(address)?;
+    if address >= chip8::XO_CHIP_MEMORY_SIZE {
+        return Err(format!("address {:#x} is outside memory", address));
+    }
+
+    Ok((address, parse_byte(byte)?))
+}
+
+fn parse_color(value: &str) -> Result<Color, String> {
+    if let Some(hex) = value.strip_prefix('#') {
+        // Checked up front so the slicing below can't split a multibyte character
+        if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("expected #RRGGBB, got '{}'", value));
+        }
+
+        let channel = |i: usize| {
+            u8::from_str_radix(&hex[i..i + 2], 16)
+                .map_err(|_| format!("invalid hex color '{}'", value))
+        };
+
+        return Ok(Color::Rgb {
+            r: channel(0)?,
+            g: channel(2)?,
+            b: channel(4)?,
+        });
+    }
+
+    Color::try_from(value).map_err(|_| format!("unknown color '{}'", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn colors_parse_hex_and_names_but_not_other_text() {
+        assert!(matches!(
+            parse_color("#33ff00"),
+            Ok(Color::Rgb {
+                r: 0x33,
+                g: 0xFF,
+                b: 0
+            })
+        ));
+        assert!(matches!(parse_color("green"), Ok(Color::Green)));
+        assert!(parse_color("#a€bc").is_err());
+        assert!(parse_color("#12345").is_err());
+    }
 }
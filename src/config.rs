@@ -0,0 +1,143 @@
+use std::{
+    ffi::OsString,
+    io::{Error, ErrorKind},
+    path::PathBuf,
+};
+
+use chip8::Quirks;
+use clap::CommandFactory;
+use serde::Deserialize;
+
+use crate::cli::CliOptions;
+
+/// Quirks set in the config file, each overriding the mode's default when present.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct QuirkSettings {
+    pub vf_reset: Option<bool>,
+    pub memory_increment: Option<bool>,
+    pub shift_vy: Option<bool>,
+    pub jump_vx: Option<bool>,
+    pub display_wait: Option<bool>,
+    pub draw_cost: Option<bool>,
+    pub collision_rows: Option<bool>,
+}
+
+impl QuirkSettings {
+    pub fn apply(&self, quirks: &mut Quirks) {
+        let settings = [
+            (self.vf_reset, &mut quirks.vf_reset),
+            (self.memory_increment, &mut quirks.memory_increment),
+            (self.shift_vy, &mut quirks.shift_vy),
+            (self.jump_vx, &mut quirks.jump_vx),
+            (self.display_wait, &mut quirks.display_wait),
+            (self.draw_cost, &mut quirks.draw_cost),
+            (self.collision_rows, &mut quirks.collision_rows),
+        ];
+        for (setting, quirk) in settings {
+            if let Some(value) = setting {
+                *quirk = value;
+            }
+        }
+    }
+}
+
+/// A config file's options, as command line arguments so they're parsed exactly like flags.
+pub struct Config {
+    pub args: Vec<OsString>,
+    pub quirks: QuirkSettings,
+}
+
+/// `$XDG_CONFIG_HOME/chip8/config.toml`, falling back to `~/.config`.
+pub fn default_path() -> Option<PathBuf> {
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_home.join("chip8").join("config.toml"))
+}
+
+impl Config {
+    pub fn open(path: &PathBuf) -> Result<Self, Error> {
+        Self::parse(&std::fs::read_to_string(path)?).map_err(|message| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), message),
+            )
+        })
+    }
+
+    /// Parses a config whose keys are `CliOptions`' long flag names, in snake case, with the
+    /// quirks in a `[quirks]` table.
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut table: toml::Table = toml::from_str(source).map_err(|e| e.to_string())?;
+        let quirks = match table.remove("quirks") {
+            Some(quirks) => quirks.try_into().map_err(|e| format!("quirks: {}", e))?,
+            None => QuirkSettings::default(),
+        };
+
+        let command = CliOptions::command();
+        let mut args = Vec::new();
+        for (key, value) in table {
+            let name = key.replace('_', "-");
+            let arg = command
+                .get_arguments()
+                .find(|arg| arg.get_long() == Some(name.as_str()) && name != "config")
+                .ok_or_else(|| format!("unknown option '{}'", key))?;
+
+            let value = match value {
+                toml::Value::String(value) => value,
+                toml::Value::Integer(value) => value.to_string(),
+                toml::Value::Float(value) => value.to_string(),
+                toml::Value::Boolean(value) if arg.get_action().takes_values() => value.to_string(),
+                toml::Value::Boolean(true) => {
+                    args.push(format!("--{}", name).into());
+                    continue;
+                }
+                toml::Value::Boolean(false) => continue,
+                _ => return Err(format!("unsupported value for '{}'", key)),
+            };
+            args.push(format!("--{}={}", name, value).into());
+        }
+
+        Ok(Self { args, quirks })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn options_become_flags_and_quirks_apply() {
+        let config = Config::parse(
+            r##"
+            speed = 1000
+            debug = true
+            strict = false
+            fg_color = "#33FF33"
+            display_wait = false
+
+            [quirks]
+            jump_vx = true
+            "##,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.args,
+            [
+                "--debug",
+                "--display-wait=false",
+                "--fg-color=#33FF33",
+                "--speed=1000"
+            ]
+        );
+
+        let mut quirks = Quirks::from(&chip8::Mode::Chip8);
+        config.quirks.apply(&mut quirks);
+        assert!(quirks.jump_vx && quirks.vf_reset);
+
+        assert!(Config::parse("bogus = 1").is_err());
+        assert!(Config::parse("[quirks]\nbogus = true").is_err());
+    }
+}
@@ -0,0 +1,130 @@
+//! The switchable panes of the `--debug` overlay: registers, memory around I, the stack and
+//! disassembly from the PC.
+
+use chip8::{decode, Chip8};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum Pane {
+    #[default]
+    Registers,
+    Memory,
+    Stack,
+    Disassembly,
+}
+
+#[derive(Default)]
+pub struct DebugView {
+    pane: Pane,
+    /// Rows scrolled from where the pane starts, e.g. the row holding I for memory.
+    scroll: isize,
+}
+
+impl DebugView {
+    pub fn next_pane(&mut self) {
+        self.pane = match self.pane {
+            Pane::Registers => Pane::Memory,
+            Pane::Memory => Pane::Stack,
+            Pane::Stack => Pane::Disassembly,
+            Pane::Disassembly => Pane::Registers,
+        };
+        self.scroll = 0;
+    }
+
+    pub fn scroll(&mut self, rows: isize) {
+        self.scroll += rows;
+    }
+
+    pub fn title(&self) -> String {
+        format!("{:?} (o: next pane, [ ] PgUp PgDn: scroll)", self.pane)
+    }
+
+    /// Up to `height` lines of the current pane.
+    pub fn lines(&self, machine: &Chip8, height: usize) -> Vec<String> {
+        // Panes without a natural middle can't scroll above their first line
+        let skip = self.scroll.max(0) as usize;
+        match self.pane {
+            Pane::Registers => machine
+                .dump_state()
+                .lines()
+                .skip(skip)
+                .take(height)
+                .map(String::from)
+                .collect(),
+            Pane::Memory => {
+                let first_row = (machine.index_register as isize / 8 + self.scroll).max(0) as usize;
+                (first_row..machine.memory.len() / 8)
+                    .take(height)
+                    .map(|row| {
+                        let bytes: Vec<String> = machine.memory[row * 8..row * 8 + 8]
+                            .iter()
+                            .map(|byte| format!("{:02X}", byte))
+                            .collect();
+                        format!("{:#06x}: {}", row * 8, bytes.join(" "))
+                    })
+                    .collect()
+            }
+            Pane::Stack if machine.stack.is_empty() => vec!["(empty)".to_string()],
+            Pane::Stack => machine
+                .stack
+                .iter()
+                .enumerate()
+                .rev()
+                .skip(skip)
+                .take(height)
+                .map(|(depth, address)| format!("{:2}: {:#06x}", depth, address))
+                .collect(),
+            Pane::Disassembly => {
+                let pc = machine.program_counter;
+                let start = (pc as isize + self.scroll * 2).max(0) as usize;
+                (start..machine.memory.len() - 1)
+                    .step_by(2)
+                    .take(height)
+                    .map(|address| {
+                        let opcode = u16::from_be_bytes([
+                            machine.memory[address],
+                            machine.memory[address + 1],
+                        ]);
+                        let marker = if address == pc { '>' } else { ' ' };
+                        format!(
+                            "{}{:#06x}: {:04X}  {}",
+                            marker,
+                            address,
+                            opcode,
+                            decode(opcode)
+                        )
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chip8::Mode;
+
+    use super::*;
+
+    #[test]
+    fn panes_cycle_and_scroll_from_their_start() {
+        let mut machine = Chip8::new(Mode::Chip8);
+        machine.load(&[0x00, 0xE0, 0x12, 0x00]).unwrap();
+        let mut view = DebugView::default();
+        assert_eq!(view.lines(&machine, 1), ["PC: 0x0200"]);
+
+        view.next_pane();
+        view.next_pane();
+        assert_eq!(view.lines(&machine, 4), ["(empty)"]);
+
+        view.next_pane();
+        view.scroll(-1);
+        assert_eq!(
+            view.lines(&machine, 3),
+            [
+                " 0x01fe: 0000  SYS 0x000",
+                ">0x0200: 00E0  CLS",
+                " 0x0202: 1200  JP 0x200"
+            ]
+        );
+    }
+}
@@ -0,0 +1,91 @@
+//! A line in the debug overlay for poking memory while the program runs, so sprites can be
+//! tweaked live. Edits use the same `ADDR=BB` form as `--set-mem`.
+
+use crossterm::event::KeyCode;
+
+use crate::cli::parse_memory_assignment;
+
+#[derive(Default)]
+pub struct MemoryEditor {
+    input: String,
+    /// The last address written, to show the bytes around it.
+    address: Option<usize>,
+    error: Option<String>,
+}
+
+impl MemoryEditor {
+    /// Handles a key, returning false once the editor is closed.
+    pub fn key(&mut self, code: KeyCode, memory: &mut [u8]) -> bool {
+        match code {
+            KeyCode::Esc => return false,
+            KeyCode::Enter => match parse_memory_assignment(&self.input) {
+                // Parsing allows XO-CHIP's 64KB, smaller than that in other modes
+                Ok((address, value)) => match memory.get_mut(address) {
+                    Some(byte) => {
+                        *byte = value;
+                        self.address = Some(address);
+                        self.error = None;
+                        self.input.clear();
+                    }
+                    None => self.error = Some(format!("address {:#x} is outside memory", address)),
+                },
+                Err(error) => self.error = Some(error),
+            },
+            KeyCode::Backspace => {
+                self.input.pop();
+            }
+            KeyCode::Char(c) if c.is_ascii_hexdigit() || "xX=".contains(c) => self.input.push(c),
+            _ => {}
+        }
+
+        true
+    }
+
+    /// The two overlay lines, the input and then the last write or error.
+    pub fn lines(&self, memory: &[u8]) -> [String; 2] {
+        let status = match (&self.error, self.address) {
+            (Some(error), _) => error.clone(),
+            (None, Some(address)) => {
+                let start = address & !7;
+                let bytes: Vec<String> = memory[start..start + 8]
+                    .iter()
+                    .map(|byte| format!("{:02X}", byte))
+                    .collect();
+                format!("{:#06x}: {}", start, bytes.join(" "))
+            }
+            (None, None) => "Enter to write, Esc to close".to_string(),
+        };
+
+        [format!("Poke: {}_", self.input), status]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn enter_pokes_memory_and_shows_the_row() {
+        let mut memory = [0; chip8::MEMORY_SIZE];
+        let mut editor = MemoryEditor::default();
+        for c in "0x30A=0xfF".chars() {
+            assert!(editor.key(KeyCode::Char(c), &mut memory));
+        }
+        editor.key(KeyCode::Enter, &mut memory);
+        assert_eq!(memory[0x30A], 0xFF);
+        assert_eq!(editor.lines(&memory)[1], "0x0308: 00 00 FF 00 00 00 00 00");
+
+        assert!(!editor.key(KeyCode::Esc, &mut memory));
+    }
+
+    #[test]
+    fn pokes_past_the_end_of_memory_are_errors() {
+        let mut memory = [0; chip8::MEMORY_SIZE];
+        let mut editor = MemoryEditor::default();
+        for c in "0x2000=01".chars() {
+            editor.key(KeyCode::Char(c), &mut memory);
+        }
+        editor.key(KeyCode::Enter, &mut memory);
+        assert_eq!(editor.lines(&memory)[1], "address 0x2000 is outside memory");
+    }
+}
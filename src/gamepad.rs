@@ -0,0 +1,123 @@
+use std::io::Error;
+
+use gilrs::{Button, EventType, Gilrs};
+
+use chip8::KeyboardState;
+
+/// D-pad on the keys most games use for directions, face and menu buttons on the rest.
+pub const DEFAULT_GAMEPAD_MAP: &str =
+    "DPadUp=2,DPadDown=8,DPadLeft=4,DPadRight=6,South=5,East=0,West=A,North=B,Select=E,Start=F";
+
+const BUTTONS: [(&str, Button); 19] = [
+    ("South", Button::South),
+    ("East", Button::East),
+    ("North", Button::North),
+    ("West", Button::West),
+    ("C", Button::C),
+    ("Z", Button::Z),
+    ("LeftTrigger", Button::LeftTrigger),
+    ("LeftTrigger2", Button::LeftTrigger2),
+    ("RightTrigger", Button::RightTrigger),
+    ("RightTrigger2", Button::RightTrigger2),
+    ("Select", Button::Select),
+    ("Start", Button::Start),
+    ("Mode", Button::Mode),
+    ("LeftThumb", Button::LeftThumb),
+    ("RightThumb", Button::RightThumb),
+    ("DPadUp", Button::DPadUp),
+    ("DPadDown", Button::DPadDown),
+    ("DPadLeft", Button::DPadLeft),
+    ("DPadRight", Button::DPadRight),
+];
+
+/// Maps gamepad buttons onto the 16 keypad values.
+#[derive(Clone, Debug)]
+pub struct GamepadMap {
+    bindings: Vec<(Button, u8)>,
+}
+
+impl GamepadMap {
+    fn key_for(&self, button: Button) -> Option<u8> {
+        self.bindings
+            .iter()
+            .find(|(b, _)| *b == button)
+            .map(|&(_, key)| key)
+    }
+}
+
+/// Parses a comma separated list of `Button=key` pairs, e.g. `DPadUp=2,South=5`.
+pub fn parse_gamepad_map(value: &str) -> Result<GamepadMap, String> {
+    let mut bindings = Vec::new();
+    for binding in value.split(',').map(str::trim).filter(|b| !b.is_empty()) {
+        let (name, key) = binding
+            .split_once('=')
+            .ok_or_else(|| format!("expected Button=key, got '{}'", binding))?;
+
+        let button = BUTTONS
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name.trim()))
+            .map(|&(_, button)| button)
+            .ok_or_else(|| format!("unknown gamepad button '{}'", name))?;
+
+        let key = u8::from_str_radix(key.trim(), 16)
+            .ok()
+            .filter(|&key| key < 16)
+            .ok_or_else(|| format!("'{}' is not a keypad value 0-F", key))?;
+
+        bindings.push((button, key));
+    }
+
+    Ok(GamepadMap { bindings })
+}
+
+/// Reads connected gamepads and tracks which keypad values they hold down.
+pub struct Gamepad {
+    gilrs: Gilrs,
+    map: GamepadMap,
+    keys_pressed: [bool; 16],
+    pressed_key: Option<u8>,
+}
+
+impl Gamepad {
+    pub fn new(map: GamepadMap) -> Result<Self, Error> {
+        Ok(Self {
+            gilrs: Gilrs::new().map_err(|e| Error::other(e.to_string()))?,
+            map,
+            keys_pressed: [false; 16],
+            pressed_key: None,
+        })
+    }
+
+    /// Drains pending gamepad events.
+    pub fn update(&mut self) {
+        self.pressed_key = None;
+        while let Some(event) = self.gilrs.next_event() {
+            match event.event {
+                EventType::ButtonPressed(button, _) => {
+                    if let Some(key) = self.map.key_for(button) {
+                        self.keys_pressed[key as usize] = true;
+                        self.pressed_key = Some(key);
+                    }
+                }
+                EventType::ButtonReleased(button, _) => {
+                    if let Some(key) = self.map.key_for(button) {
+                        self.keys_pressed[key as usize] = false;
+                    }
+                }
+                EventType::Disconnected => {
+                    self.keys_pressed = [false; 16];
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Adds the gamepad's keys to `state`, so either source can press a key.
+    pub fn merge_into(&self, state: &mut KeyboardState) {
+        for (pressed, &held) in state.keys_pressed.iter_mut().zip(&self.keys_pressed) {
+            *pressed |= held;
+        }
+
+        state.pressed_key = state.pressed_key.or(self.pressed_key);
+    }
+}
@@ -0,0 +1,140 @@
+use std::io::{Error, Write};
+
+use chip8::{decode, Chip8, Mode, Opcode};
+
+use crate::romdb::{rom_hash, RomProfile};
+
+/// What each opcode group, by high nibble, does.
+const GROUPS: [&str; 16] = [
+    "0NNN system",
+    "1NNN jump",
+    "2NNN call",
+    "3XNN skip if equal",
+    "4XNN skip if not equal",
+    "5XY0 skip if registers equal",
+    "6XNN load",
+    "7XNN add",
+    "8XYN arithmetic",
+    "9XY0 skip if registers differ",
+    "ANNN load index",
+    "BNNN jump with offset",
+    "CXNN random",
+    "DXYN draw",
+    "EXNN key skip",
+    "FXNN timers, keys and memory",
+];
+
+/// Describes the program loaded into `chip8` and the settings it will run with.
+pub fn print(program: &[u8], profile: Option<&RomProfile>, chip8: &Chip8) {
+    println!("Size: {} bytes", program.len());
+    println!("SHA-1: {}", rom_hash(program));
+    match profile {
+        Some(profile) => println!("Profile: {} ({})", profile.title, profile.platform),
+        None => println!("Profile: not in the ROM database"),
+    }
+    println!("Mode: {:?}", chip8.mode);
+    println!("Quirks: {:?}", chip8.quirks);
+
+    // Data mixed into the code is counted too, so treat this as a rough guide.
+    let mut counts = [0; 16];
+    for (_, opcode, _) in chip8.instructions() {
+        counts[(opcode >> 12) as usize] += 1;
+    }
+
+    println!("Opcodes:");
+    for (group, count) in GROUPS.iter().zip(counts) {
+        if count > 0 {
+            println!("  {:<32} {}", group, count);
+        }
+    }
+}
+
+/// Prints which kinds of instruction ran out of all of them.
+pub fn print_coverage(covered: &[&str]) {
+    let missed: Vec<&str> = Opcode::TEMPLATES
+        .iter()
+        .copied()
+        .filter(|template| !covered.contains(template))
+        .collect();
+
+    println!(
+        "Instructions covered: {} of {}",
+        covered.len(),
+        Opcode::TEMPLATES.len()
+    );
+    println!("  Used:     {}", covered.join(" "));
+    println!("  Not used: {}", missed.join(" "));
+}
+
+/// Prints how many instructions ran in each opcode group, most first.
+pub fn print_profile(counts: &[u64; 16]) {
+    let total: u64 = counts.iter().sum();
+    let mut groups: Vec<_> = GROUPS
+        .iter()
+        .zip(counts)
+        .filter(|(_, &count)| count > 0)
+        .collect();
+    groups.sort_by(|a, b| b.1.cmp(a.1));
+
+    println!("Instructions executed: {}", total);
+    for (group, count) in groups {
+        println!(
+            "  {:<32} {:>12} {:>5.1}%",
+            group,
+            count,
+            *count as f64 * 100.0 / total as f64
+        );
+    }
+}
+
+/// Decodes the loaded program without running it, writing each word that isn't an instruction
+/// in the mode. Returns whether every word decoded.
+pub fn validate(chip8: &Chip8, mut out: impl Write) -> Result<bool, Error> {
+    let xo_chip = matches!(chip8.mode, Mode::XoChip);
+    let mut invalid = 0;
+    let mut operand = false;
+    for (address, opcode, _) in chip8.instructions() {
+        // F000's second word is an address, not an instruction
+        if std::mem::take(&mut operand) {
+            continue;
+        }
+
+        let reason = match decode(opcode) {
+            Opcode::Unknown(_) => "unknown opcode",
+            instruction if instruction.is_xo_chip() && !xo_chip => "XO-CHIP only",
+            Opcode::LoadLongIndex => {
+                operand = true;
+                continue;
+            }
+            _ => continue,
+        };
+
+        writeln!(out, "{:04X}: {:04X}  {}", address, opcode, reason)?;
+        invalid += 1;
+    }
+
+    writeln!(out, "{} invalid instructions", invalid)?;
+    Ok(invalid == 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_reports_words_that_dont_decode() {
+        let mut chip8 = Chip8::new(Mode::XoChip);
+        chip8
+            .load(&[0x00, 0xE0, 0xF0, 0x00, 0x51, 0x23, 0x51, 0x21])
+            .unwrap();
+        let mut out = Vec::new();
+        assert!(!validate(&chip8, &mut out).unwrap());
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "0206: 5121  unknown opcode\n1 invalid instructions\n"
+        );
+
+        chip8.set_mode(Mode::Chip8);
+        assert!(!validate(&chip8, std::io::sink()).unwrap());
+    }
+}
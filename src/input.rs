@@ -0,0 +1,335 @@
+use std::{
+    io::Error,
+    time::{Duration, Instant},
+};
+
+use chip8::KeyboardState;
+use crossterm::event::{read, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+
+use crate::keymap::Keymap;
+
+/// Keys that control the emulator rather than the program. Keys bound in the
+/// keymap take precedence.
+pub enum Control {
+    Quit,
+    CycleMode,
+    /// Fast forward while held.
+    Turbo(bool),
+    /// Halve the speed, for slow motion.
+    SlowDown,
+    /// Double the speed, back up to normal.
+    SpeedUp,
+    /// Jump to one of the --speed-presets, bound to F1 onwards.
+    SpeedPreset(usize),
+    Pause,
+    /// Pause and run a single frame.
+    FrameAdvance,
+    /// Pause and step back a frame.
+    Rewind,
+    /// The terminal lost (false) or regained (true) focus.
+    Focus(bool),
+    /// Open the memory editor in the debug overlay.
+    EditMemory,
+    /// Reset and run the next of several programs.
+    NextProgram,
+    /// The terminal changed size, so everything needs drawing again.
+    Resize,
+    /// Switch the debug overlay to its next pane.
+    NextPane,
+    /// Scroll the debug pane by this many rows.
+    Scroll(isize),
+    /// Text pasted into the terminal, to load as a hex program.
+    Paste(String),
+}
+
+fn control_for(code: KeyCode, kind: KeyEventKind) -> Option<Control> {
+    match (code, kind) {
+        (KeyCode::Char('m'), KeyEventKind::Press) => Some(Control::CycleMode),
+        (KeyCode::Char('-'), KeyEventKind::Press) => Some(Control::SlowDown),
+        (KeyCode::Char('='), KeyEventKind::Press) => Some(Control::SpeedUp),
+        (KeyCode::Char('p'), KeyEventKind::Press) => Some(Control::Pause),
+        (KeyCode::Char('i'), KeyEventKind::Press) => Some(Control::EditMemory),
+        (KeyCode::Char('n'), KeyEventKind::Press) => Some(Control::NextProgram),
+        (KeyCode::Char('o'), KeyEventKind::Press) => Some(Control::NextPane),
+        // Not the arrows, which --layout modern binds to the keypad
+        (KeyCode::Char('['), KeyEventKind::Press | KeyEventKind::Repeat) => {
+            Some(Control::Scroll(-1))
+        }
+        (KeyCode::Char(']'), KeyEventKind::Press | KeyEventKind::Repeat) => {
+            Some(Control::Scroll(1))
+        }
+        (KeyCode::PageUp, KeyEventKind::Press | KeyEventKind::Repeat) => Some(Control::Scroll(-8)),
+        (KeyCode::PageDown, KeyEventKind::Press | KeyEventKind::Repeat) => Some(Control::Scroll(8)),
+        (KeyCode::F(n @ 1..), KeyEventKind::Press) => Some(Control::SpeedPreset(n as usize - 1)),
+        (KeyCode::Char('.'), KeyEventKind::Press | KeyEventKind::Repeat) => {
+            Some(Control::FrameAdvance)
+        }
+        (KeyCode::Char(','), KeyEventKind::Press | KeyEventKind::Repeat) => Some(Control::Rewind),
+        (KeyCode::Tab, KeyEventKind::Press | KeyEventKind::Repeat) => Some(Control::Turbo(true)),
+        (KeyCode::Tab, KeyEventKind::Release) => Some(Control::Turbo(false)),
+        _ => None,
+    }
+}
+
+/// Releases keys on terminals that only report presses, by treating a key as
+/// held until it hasn't repeated for a while.
+pub struct ReleaseTimeout {
+    hold: Duration,
+    last_seen: [Option<Instant>; 16],
+    turbo_seen: Option<Instant>,
+}
+
+impl ReleaseTimeout {
+    pub fn new(hold: Duration) -> Self {
+        Self {
+            hold,
+            last_seen: [None; 16],
+            turbo_seen: None,
+        }
+    }
+
+    /// Releases keys that have timed out, returning the turbo release if it has too.
+    fn expire(&mut self, state: &mut KeyboardState) -> Option<Control> {
+        let now = Instant::now();
+        for (key, last_seen) in self.last_seen.iter_mut().enumerate() {
+            if last_seen.is_some_and(|seen| now - seen > self.hold) {
+                *last_seen = None;
+                state.keys_pressed[key] = false;
+            }
+        }
+
+        if self.turbo_seen.is_some_and(|seen| now - seen > self.hold) {
+            self.turbo_seen = None;
+            return Some(Control::Turbo(false));
+        }
+
+        None
+    }
+}
+
+/// Polls for a key press without touching the keypad, for the memory editor.
+pub fn read_key(timeout: &Duration) -> Result<Option<KeyCode>, Error> {
+    if crossterm::event::poll(*timeout)? {
+        match read()? {
+            // Leave the editor rather than typing a c
+            Event::Key(KeyEvent {
+                code: KeyCode::Char('c'),
+                modifiers: KeyModifiers::CONTROL,
+                ..
+            }) => return Ok(Some(KeyCode::Esc)),
+            Event::Key(KeyEvent {
+                code,
+                kind: KeyEventKind::Press | KeyEventKind::Repeat,
+                ..
+            }) => return Ok(Some(code)),
+            _ => {}
+        }
+    }
+
+    Ok(None)
+}
+
+/// Somewhere key events come from, so the keypad logic doesn't depend on a real terminal.
+pub trait InputSource {
+    /// Every event that's pending, waiting up to `timeout` for the first.
+    fn poll_events(&mut self, timeout: &Duration) -> Result<Vec<Event>, Error>;
+}
+
+/// Events from the terminal through crossterm.
+pub struct TerminalInput;
+
+impl InputSource for TerminalInput {
+    fn poll_events(&mut self, timeout: &Duration) -> Result<Vec<Event>, Error> {
+        let mut events = Vec::new();
+        // Only the first poll waits, the rest just empty the queue
+        let mut timeout = *timeout;
+        while crossterm::event::poll(timeout)? {
+            timeout = Duration::ZERO;
+            events.push(read()?);
+        }
+
+        Ok(events)
+    }
+}
+
+/// Drains `source`'s pending events into the keypad state, returning the emulator controls
+/// pressed in order.
+pub fn update_keyboard_state(
+    state: &mut KeyboardState,
+    source: &mut impl InputSource,
+    keymap: &Keymap,
+    mut release_timeout: Option<&mut ReleaseTimeout>,
+    timeout: &Duration,
+) -> Result<Vec<Control>, Error> {
+    let mut controls = Vec::new();
+    if let Some(release_timeout) = release_timeout.as_deref_mut() {
+        controls.extend(release_timeout.expire(state));
+    }
+
+    for event in source.poll_events(timeout)? {
+        controls.extend(apply_event(
+            state,
+            keymap,
+            release_timeout.as_deref_mut(),
+            event,
+        ));
+    }
+
+    Ok(controls)
+}
+
+/// Applies a single event to the keypad state. Presses stay latched in `pressed_key` until the
+/// caller clears it, so a tap released before the program next looks still reaches `FX0A`.
+fn apply_event(
+    state: &mut KeyboardState,
+    keymap: &Keymap,
+    release_timeout: Option<&mut ReleaseTimeout>,
+    event: Event,
+) -> Option<Control> {
+    match event {
+        Event::Key(KeyEvent {
+            code,
+            modifiers,
+            kind: KeyEventKind::Press,
+            ..
+        }) if keymap.is_quit(code, modifiers) => Some(Control::Quit),
+        Event::Key(KeyEvent { code, kind, .. }) => {
+            let Some(key) = keymap.key_for(code) else {
+                let control = control_for(code, kind);
+                if let (Some(Control::Turbo(true)), Some(release_timeout)) =
+                    (&control, release_timeout)
+                {
+                    release_timeout.turbo_seen = Some(Instant::now());
+                }
+
+                return control;
+            };
+
+            let i = key as usize;
+            if let Some(release_timeout) = release_timeout {
+                release_timeout.last_seen[i] = Some(Instant::now());
+            }
+
+            match kind {
+                KeyEventKind::Press => {
+                    state.keys_pressed[i] = true;
+                    state.pressed_key = Some(key);
+                }
+                KeyEventKind::Release => {
+                    state.keys_pressed[i] = false;
+                }
+                KeyEventKind::Repeat => {
+                    state.keys_pressed[i] = true;
+                }
+            }
+
+            None
+        }
+        Event::FocusLost => Some(Control::Focus(false)),
+        Event::FocusGained => Some(Control::Focus(true)),
+        Event::Resize(..) => Some(Control::Resize),
+        Event::Paste(text) => Some(Control::Paste(text)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossterm::event::KeyEventState;
+
+    use super::*;
+    use crate::keymap::Layout;
+
+    fn key(code: KeyCode, kind: KeyEventKind) -> Event {
+        Event::Key(KeyEvent {
+            code,
+            modifiers: KeyModifiers::NONE,
+            kind,
+            state: KeyEventState::NONE,
+        })
+    }
+
+    /// Hands out a fixed list of events on the first poll.
+    struct Scripted(Vec<Event>);
+
+    impl InputSource for Scripted {
+        fn poll_events(&mut self, _: &Duration) -> Result<Vec<Event>, Error> {
+            Ok(std::mem::take(&mut self.0))
+        }
+    }
+
+    #[test]
+    fn a_tap_between_frames_stays_latched() {
+        let keymap = Keymap::default();
+        let code = KeyCode::Char('w');
+        let mut source = Scripted(vec![
+            key(code, KeyEventKind::Press),
+            key(KeyCode::Char('p'), KeyEventKind::Press),
+            key(code, KeyEventKind::Release),
+        ]);
+        let mut state = KeyboardState::new();
+        let controls =
+            update_keyboard_state(&mut state, &mut source, &keymap, None, &Duration::ZERO).unwrap();
+
+        assert!(matches!(controls[..], [Control::Pause]));
+        assert_eq!(state.pressed_key, keymap.key_for(code));
+        assert!(!state.keys_pressed.iter().any(|&pressed| pressed));
+    }
+
+    #[test]
+    fn debug_pane_keys_are_reachable_with_every_layout() {
+        for layout in [Layout::Classic, Layout::Modern] {
+            let mut keymap = Keymap::default();
+            keymap.add_layout(layout);
+            let control = |code| {
+                let event = key(code, KeyEventKind::Press);
+                apply_event(&mut KeyboardState::new(), &keymap, None, event)
+            };
+            assert!(matches!(
+                control(KeyCode::Char('o')),
+                Some(Control::NextPane)
+            ));
+            for (code, rows) in [
+                (KeyCode::Char('['), -1),
+                (KeyCode::Char(']'), 1),
+                (KeyCode::PageUp, -8),
+                (KeyCode::PageDown, 8),
+            ] {
+                assert!(matches!(control(code), Some(Control::Scroll(r)) if r == rows));
+            }
+        }
+    }
+
+    #[test]
+    fn modern_layout_adds_arrows_and_space() {
+        let mut keymap = Keymap::default();
+        keymap.add_layout(Layout::Modern);
+        let mut state = KeyboardState::new();
+        for code in [KeyCode::Up, KeyCode::Char(' ')] {
+            apply_event(&mut state, &keymap, None, key(code, KeyEventKind::Press));
+        }
+
+        assert!(state.keys_pressed[0x2] && state.keys_pressed[0x5]);
+        assert_eq!(keymap.key_for(KeyCode::Char('x')), Some(0));
+    }
+
+    #[test]
+    fn only_the_chosen_quit_key_quits() {
+        let mut keymap = Keymap::default();
+        keymap.set_quit_key("esc".parse().unwrap()).unwrap();
+        let ctrl_c = Event::Key(KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL));
+        let mut state = KeyboardState::new();
+        assert!(apply_event(&mut state, &keymap, None, ctrl_c).is_none());
+        assert!(matches!(
+            apply_event(
+                &mut state,
+                &keymap,
+                None,
+                key(KeyCode::Esc, KeyEventKind::Press)
+            ),
+            Some(Control::Quit)
+        ));
+
+        assert!(keymap.set_quit_key("w".parse().unwrap()).is_err());
+    }
+}
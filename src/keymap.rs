@@ -0,0 +1,173 @@
+use std::{fs, path::Path, str::FromStr};
+
+use crossterm::event::{KeyCode, KeyModifiers};
+
+/// The classic layout, where the left side of a QWERTY keyboard mirrors the
+/// COSMAC VIP hex keypad. Each character is the key for keypad value 0x0-0xF.
+pub const DEFAULT_KEYMAP: &str = "x123qweasdzc4rfv";
+
+/// Keys added by `--layout modern` on top of the keymap: arrows for the directions most games
+/// read from 2, 4, 6 and 8, space for 5, the usual action key, and enter for F.
+const MODERN_BINDINGS: [(KeyCode, u8); 6] = [
+    (KeyCode::Up, 0x2),
+    (KeyCode::Left, 0x4),
+    (KeyCode::Right, 0x6),
+    (KeyCode::Down, 0x8),
+    (KeyCode::Char(' '), 0x5),
+    (KeyCode::Enter, 0xF),
+];
+
+/// Ready made additions to the keymap.
+#[derive(Clone, Copy, Debug, Default, clap::ValueEnum)]
+pub enum Layout {
+    /// Only the keymap, the hex keypad on the left of the keyboard by default.
+    #[default]
+    Classic,
+    /// The keymap plus arrows, space and enter for the keys games commonly use.
+    Modern,
+}
+
+/// The key that quits, such as `ctrl-c`, `esc` or `q`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QuitKey {
+    code: KeyCode,
+    control: bool,
+}
+
+impl Default for QuitKey {
+    fn default() -> Self {
+        Self {
+            code: KeyCode::Char('c'),
+            control: true,
+        }
+    }
+}
+
+impl FromStr for QuitKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lower = s.to_ascii_lowercase();
+        let (control, key) = match lower.strip_prefix("ctrl-") {
+            Some(key) => (true, key),
+            None => (false, lower.as_str()),
+        };
+        let mut chars = key.chars();
+        let code = match (key, chars.next(), chars.next()) {
+            ("esc", _, _) => KeyCode::Esc,
+            (_, Some(c), None) => KeyCode::Char(c),
+            _ => {
+                return Err(format!(
+                    "expected a character, esc or ctrl-<key>, got '{}'",
+                    s
+                ))
+            }
+        };
+
+        Ok(Self { code, control })
+    }
+}
+
+impl QuitKey {
+    pub fn matches(&self, code: KeyCode, modifiers: KeyModifiers) -> bool {
+        code == self.code && modifiers.contains(KeyModifiers::CONTROL) == self.control
+    }
+}
+
+/// Maps terminal keys onto the 16 keypad values, and which key quits.
+#[derive(Clone, Debug)]
+pub struct Keymap {
+    bindings: Vec<(KeyCode, u8)>,
+    quit: QuitKey,
+}
+
+impl Keymap {
+    /// The keypad value bound to `code`, if any.
+    pub fn key_for(&self, code: KeyCode) -> Option<u8> {
+        self.bindings
+            .iter()
+            .find(|(key, _)| *key == code)
+            .map(|&(_, value)| value)
+    }
+
+    /// Adds the layout's bindings, unless the keymap already uses those keys.
+    pub fn add_layout(&mut self, layout: Layout) {
+        if let Layout::Modern = layout {
+            for (code, key) in MODERN_BINDINGS {
+                if self.key_for(code).is_none() {
+                    self.bindings.push((code, key));
+                }
+            }
+        }
+    }
+
+    pub fn is_quit(&self, code: KeyCode, modifiers: KeyModifiers) -> bool {
+        self.quit.matches(code, modifiers)
+    }
+
+    /// Quit with `quit` instead, as long as it isn't also a keypad key.
+    pub fn set_quit_key(&mut self, quit: QuitKey) -> Result<(), String> {
+        if let Some(key) = self.key_for(quit.code).filter(|_| !quit.control) {
+            return Err(format!(
+                "the quit key {:?} is also bound to keypad key {:X}",
+                quit.code, key
+            ));
+        }
+
+        self.quit = quit;
+        Ok(())
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        DEFAULT_KEYMAP.parse().unwrap()
+    }
+}
+
+impl FromStr for Keymap {
+    type Err = String;
+
+    /// Parses a 16 character string where the nth character is bound to keypad value n.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let keys: Vec<char> = s.chars().collect();
+        if keys.len() != 16 {
+            return Err(format!(
+                "expected 16 keys, one per keypad value, got {}",
+                keys.len()
+            ));
+        }
+
+        for (i, key) in keys.iter().enumerate() {
+            if keys[..i].contains(key) {
+                return Err(format!("'{}' is bound more than once", key));
+            }
+        }
+
+        Ok(Self {
+            bindings: keys
+                .into_iter()
+                .enumerate()
+                .map(|(i, key)| (KeyCode::Char(key), i as u8))
+                .collect(),
+            quit: QuitKey::default(),
+        })
+    }
+}
+
+/// Parses a keymap from either a path to a file containing the layout or the layout itself.
+///
+/// Keymap files may contain blank lines and `#` comments around the layout.
+pub fn parse_keymap(value: &str) -> Result<Keymap, String> {
+    if !Path::new(value).is_file() {
+        return value.parse();
+    }
+
+    let contents = fs::read_to_string(value).map_err(|e| e.to_string())?;
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect::<String>()
+        .parse()
+}
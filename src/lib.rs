@@ -0,0 +1,16 @@
+//! A CHIP-8 interpreter core, independent of any particular frontend.
+//!
+//! Disabling the default `std` feature builds the core as `no_std`, needing only `alloc`.
+
+#![cfg_attr(all(not(feature = "std"), not(test)), no_std)]
+
+extern crate alloc;
+
+pub mod asm;
+mod chip8;
+#[cfg(feature = "wasm")]
+mod wasm;
+
+pub use crate::chip8::*;
+#[cfg(feature = "wasm")]
+pub use crate::wasm::Emulator;
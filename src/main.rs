@@ -1,188 +1,689 @@
 use std::{
-    io::{stdout, Error, Write},
-    process::exit,
-    time::{self, Duration},
+    cell::Cell,
+    collections::HashSet,
+    io::{stdout, Error, ErrorKind, Write},
+    rc::Rc,
+    thread, time,
 };
 
-use chip8::{decode, Chip8, KeyboardState};
-use clap::Parser;
+use chip8::{KeyboardState, Mode};
 use cli::CliOptions;
-use crossterm::{
-    cursor,
-    event::{
-        read, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
-        PushKeyboardEnhancementFlags,
-    },
-    execute,
-    style::Print,
-    terminal, ExecutableCommand, QueueableCommand,
-};
+use crossterm::{cursor, style::Print, terminal, QueueableCommand};
+use debug::DebugView;
+use editor::MemoryEditor;
+use input::{update_keyboard_state, Control, ReleaseTimeout, TerminalInput};
+use render::DebugOverlay;
+use rewind::Rewind;
+use romdb::RomDatabase;
+use stats::RateCounter;
+use tui::TerminalGuard;
 
-mod chip8;
 mod cli;
+mod config;
+mod debug;
+mod editor;
+#[cfg(feature = "gamepad")]
+mod gamepad;
+mod info;
+mod input;
+mod keymap;
+mod monitor;
+mod net;
+mod output;
+mod phosphor;
+mod render;
+mod replay;
+mod rewind;
+mod rom;
+mod romdb;
+mod selftest;
+mod stats;
+mod tui;
+#[cfg(feature = "watch")]
+mod watch;
+
+/// The display refresh rate, which the timers and drawing are tied to.
+const FRAMES_PER_SECOND: u64 = 60;
+/// How many frames run per displayed frame while turbo is held.
+const TURBO_FRAMES: u64 = 5;
+/// The slowest slow motion, as a fraction of normal speed, and the fastest speed preset.
+const MAX_SLOWDOWN: u64 = 16;
+
+/// Shows or hides a note just right of the display's top corner, as a visible beep.
+fn draw_beep(beeping: bool) -> Result<(), Error> {
+    if terminal::size()?.0 <= 64 {
+        return Ok(());
+    }
 
-fn draw(display: &[[u8; 64]; 32]) -> Result<(), Error> {
     let mut stdout = stdout();
-    for (i, row) in display.iter().enumerate() {
-        stdout.queue(cursor::MoveTo(0, i as u16))?;
-        for &pixel in row.iter() {
-            if pixel == 0 {
-                stdout.queue(Print(" "))?;
-            } else {
-                stdout.queue(Print("█"))?;
-            }
-        }
+    stdout
+        .queue(cursor::MoveTo(64, 0))?
+        .queue(Print(if beeping { '♪' } else { ' ' }))?;
+    stdout.flush()
+}
+
+/// Shows a one line message under the display, e.g. for warnings that can't go to stderr
+/// while the display is up.
+fn draw_status(message: &str) -> Result<(), Error> {
+    if terminal::size()?.1 <= 32 {
+        return Ok(());
     }
 
-    stdout.flush()?;
+    let mut stdout = stdout();
+    stdout
+        .queue(cursor::MoveTo(0, 32))?
+        .queue(Print(message))?
+        .queue(terminal::Clear(terminal::ClearType::UntilNewLine))?;
+    stdout.flush()
+}
 
-    Ok(())
+fn assemble(source: &str, output: &str) -> Result<(), Error> {
+    let rom = chip8::asm::assemble(&std::fs::read_to_string(source)?)
+        .map_err(|e| Error::new(ErrorKind::InvalidData, format!("{}: {}", source, e)))?;
+    std::fs::write(output, rom)
 }
 
-fn draw_debug(machine: &Chip8, keyboard: &KeyboardState) -> Result<(), Error> {
-    const DEBUG_COLUMN: u16 = 66;
-    let mut stdout = stdout();
-    let info: [String; 8] = [
-        format!("PC: {:#06x}", machine.program_counter),
-        format!("I: {:#06x}", machine.index_register),
-        format!("DT: {:#04x}", machine.delay_timer),
-        format!("ST: {:#04x}", machine.sound_timer),
-        format!("SP: {:#04x}", machine.stack_pointer),
-        format!("Mode: {:?}", machine.mode),
-        format!("Key: {:?}", keyboard.pressed_key),
-        format!(
-            "Pressed: {:?}",
-            keyboard
-                .keys_pressed
-                .iter()
-                .enumerate()
-                .filter(|(_, &v)| v)
-                .map(|(i, _)| format!("{:#x}", i))
-                .collect::<Vec<_>>()
-        ),
-    ];
-
-    for (i, line) in info.iter().enumerate() {
-        stdout
-            .queue(cursor::MoveTo(DEBUG_COLUMN, i as u16))?
-            .queue(Print(line.as_str()))?
-            .queue(terminal::Clear(terminal::ClearType::UntilNewLine))?;
-    }
-
-    stdout.flush()?;
+fn bench(chip8: &mut chip8::Chip8, count: u64) -> Result<(), Error> {
+    let keyboard_state = KeyboardState::new();
+    let start = time::Instant::now();
+    for _ in 0..count {
+        chip8.step(&keyboard_state)?;
+        if chip8.is_waiting_for_vblank() {
+            // No frames to wait for, let the draw through straight away
+            chip8.vblank();
+        }
+    }
 
+    let elapsed = start.elapsed();
+    println!(
+        "{} instructions in {:.3}s, {:.0} instructions/s",
+        count,
+        elapsed.as_secs_f64(),
+        count as f64 / elapsed.as_secs_f64()
+    );
     Ok(())
 }
 
+/// Runs `count` instructions a frame at a time with no keys pressed, then prints the display's
+/// hash, or the whole state as JSON, checking the hash against `expected` when given.
+fn run_headless(
+    chip8: &mut chip8::Chip8,
+    count: u64,
+    cycles_per_frame: u64,
+    expected: Option<&str>,
+    json: bool,
+) -> Result<(), Error> {
+    let keyboard_state = KeyboardState::new();
+    let end = chip8.cycles() + count;
+    while chip8.cycles() < end {
+        let cycles = cycles_per_frame.min(end - chip8.cycles());
+        chip8.run_cycles(cycles as usize, &keyboard_state)?;
+        chip8.tick_timers();
+        chip8.vblank();
+    }
+
+    let hash = output::display_hash(&chip8.display_bits());
+    // Only the result goes to stdout, logs go to stderr
+    if json {
+        println!("{}", output::state_json(chip8)?);
+    } else {
+        println!("{}", hash);
+    }
+
+    match expected {
+        Some(expected) if !expected.eq_ignore_ascii_case(&hash) => Err(Error::other(format!(
+            "the display's SHA-1 is {}, expected {}",
+            hash, expected
+        ))),
+        _ => Ok(()),
+    }
+}
+
 fn main() -> Result<(), Error> {
-    let options = CliOptions::parse();
-    let speed_duration = time::Duration::from_nanos(1_000_000_000 / options.speed);
+    let options = CliOptions::load()?;
+    env_logger::Builder::new()
+        .filter_level(options.log_level)
+        .target(env_logger::Target::Stderr)
+        .init();
 
-    terminal::enable_raw_mode()?;
+    if let Some(source) = options.assemble.as_deref() {
+        return assemble(source, options.output.as_deref().unwrap_or_default());
+    }
 
-    let mut stdout = stdout();
-    execute!(
-        stdout,
-        PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES)
-    )?;
+    if let Some(address) = options.connect.as_deref() {
+        let guard = TerminalGuard::new()?;
+        let release_timeout = (!guard.reports_key_releases())
+            .then(|| ReleaseTimeout::new(time::Duration::from_millis(options.key_hold_ms)));
+        return net::connect(
+            address,
+            &options.keymap,
+            release_timeout,
+            render::renderer(&options).as_mut(),
+        );
+    }
 
-    stdout.execute(cursor::Hide)?;
-    stdout.execute(terminal::Clear(terminal::ClearType::All))?;
+    let program_limit = chip8::XO_CHIP_MEMORY_SIZE.saturating_sub(options.load_address);
+    let mut programs = Vec::new();
+    for location in &options.program {
+        let program = rom::read_program(location, options.entry.as_deref(), program_limit)?;
+        log::info!("read {} bytes from {}", program.len(), location);
+        programs.push(program);
+    }
 
-    let mut chip8 = chip8::Chip8::new(options.mode);
-    let program = std::fs::read(&options.program)?;
-    chip8.load(&program);
+    // Settings are picked for the first program and kept for the rest of the playlist
+    let mut current = 0;
+    let program = programs.first().map(Vec::as_slice).unwrap_or_default();
+    let database = match options.rom_db.as_deref() {
+        Some(path) => RomDatabase::open(path)?,
+        None => RomDatabase::embedded()?,
+    };
 
-    let mut timer = time::Instant::now();
-    let mut keyboard_state = KeyboardState::new();
-    loop {
-        let start = time::Instant::now();
-        let opcode = chip8.fetch();
+    let profile = database.lookup(program);
+    let mode = match (options.platform, options.mode.clone(), profile.as_ref()) {
+        (Some(platform), _, _) => platform.mode(),
+        (None, Some(mode), _) => mode,
+        (None, None, Some(profile)) => profile.mode.clone(),
+        (None, None, None) => Mode::Chip48,
+    };
 
-        let instruction = decode(opcode);
-        update_keyboard_state(&mut keyboard_state, &speed_duration)?;
-        let action = chip8.execute(&instruction, &keyboard_state)?;
+    let mut chip8 = chip8::Chip8::new(mode);
+    if let Some(profile) = profile.as_ref() {
+        eprintln!(
+            "Matched {} ({}), running as {:?}",
+            profile.title, profile.platform, chip8.mode
+        );
 
-        // Attempt to evaluate around 1000 ops per second
-        while time::Instant::now() - start < speed_duration {}
+        if options.mode.is_none() {
+            chip8.quirks = profile.quirks;
+        }
+    }
 
-        // Redraw the display
-        match action {
-            chip8::Actions::Redraw => {
-                draw(&chip8.display)?;
-            }
-            chip8::Actions::None => {}
+    if let Some(platform) = options.platform {
+        chip8.quirks = platform.quirks();
+    }
+
+    options.quirks.apply(&mut chip8.quirks);
+    chip8.strict = options.strict;
+    chip8.protect_interpreter = options.protect_interpreter;
+    chip8.max_stack_depth = options.stack_depth;
+    if let Some(display_wait) = options.display_wait {
+        chip8.quirks.display_wait = display_wait;
+    }
+
+    if let Some(draw_cost) = options.draw_cost {
+        chip8.quirks.draw_cost = draw_cost;
+    }
+
+    if let Some(seed) = options.seed {
+        chip8.set_seed(seed);
+    }
+
+    if let Some(path) = options.font.as_deref() {
+        chip8.load_font(&std::fs::read(path)?)?;
+    }
+
+    let speed = options.speed(&chip8.mode);
+    log::info!(
+        "running as {:?} with {:?} at {} instructions per second",
+        chip8.mode,
+        chip8.quirks,
+        speed
+    );
+    chip8.set_load_address(options.load_address)?;
+    chip8.load(program)?;
+
+    if let Some(path) = options.rpl_file.as_deref() {
+        // A missing file just means nothing has been saved yet
+        if let Ok(flags) = std::fs::read(path) {
+            let count = flags.len().min(chip8.rpl_flags.len());
+            chip8.rpl_flags[..count].copy_from_slice(&flags[..count]);
         }
+    }
 
-        if options.debug {
-            draw_debug(&chip8, &keyboard_state)?;
+    for &(register, value) in &options.set_reg {
+        chip8.registers[register] = value;
+    }
+
+    for &(address, value) in &options.set_mem {
+        *chip8
+            .memory
+            .get_mut(address)
+            .ok_or(chip8::Chip8Error::AddressOutOfRange { address })? = value;
+    }
+
+    if options.profile {
+        chip8.enable_profiling();
+    }
+
+    if options.coverage {
+        chip8.enable_coverage();
+    }
+
+    if options.info {
+        info::print(program, profile.as_ref(), &chip8);
+        return Ok(());
+    }
+
+    if options.selftest {
+        return match selftest::run(&chip8, stdout())? {
+            true => Ok(()),
+            false => Err(Error::other("some quirks didn't behave as configured")),
+        };
+    }
+
+    if options.validate {
+        return match info::validate(&chip8, stdout())? {
+            true => Ok(()),
+            false => Err(Error::other("the program has invalid instructions")),
+        };
+    }
+
+    if options.disassemble {
+        for (address, opcode, mnemonic) in chip8.instructions() {
+            println!("{:04X}: {:04X}  {}", address, opcode, mnemonic);
         }
 
-        // Update delay and sound timer at 60hz
-        if time::Instant::now() - timer > time::Duration::from_millis(1_000 / 60) {
-            timer = time::Instant::now();
-            if chip8.delay_timer > 0 {
-                chip8.delay_timer -= 1;
-            }
+        return Ok(());
+    }
 
-            if chip8.sound_timer > 0 {
-                chip8.sound_timer -= 1;
-            }
+    if options.monitor {
+        return monitor::run(&mut chip8, std::io::stdin().lock(), stdout());
+    }
+
+    if let Some(address) = options.serve.as_deref() {
+        return net::serve(&mut chip8, address, speed);
+    }
+
+    if let Some(count) = options.max_cycles {
+        let cycles_per_frame = (speed / FRAMES_PER_SECOND).max(1);
+        return run_headless(
+            &mut chip8,
+            count,
+            cycles_per_frame,
+            options.expect_hash.as_deref(),
+            options.json,
+        );
+    }
+
+    if let Some(count) = options.bench {
+        bench(&mut chip8, count)?;
+        if let Some(profile) = chip8.profile() {
+            info::print_profile(profile);
+        }
+
+        if let Some(covered) = chip8.coverage() {
+            info::print_coverage(&covered);
         }
+
+        return Ok(());
     }
-}
 
-fn update_keyboard_state(state: &mut KeyboardState, timeout: &Duration) -> Result<(), Error> {
-    const KEYS: [KeyCode; 16] = [
-        KeyCode::Char('x'),
-        KeyCode::Char('1'),
-        KeyCode::Char('2'),
-        KeyCode::Char('3'),
-        KeyCode::Char('q'),
-        KeyCode::Char('w'),
-        KeyCode::Char('e'),
-        KeyCode::Char('a'),
-        KeyCode::Char('s'),
-        KeyCode::Char('d'),
-        KeyCode::Char('z'),
-        KeyCode::Char('c'),
-        KeyCode::Char('4'),
-        KeyCode::Char('r'),
-        KeyCode::Char('f'),
-        KeyCode::Char('v'),
-    ];
-
-    state.pressed_key = None;
-    if crossterm::event::poll(*timeout)? {
-        match read()? {
-            Event::Key(KeyEvent {
-                code: KeyCode::Char('c'),
-                modifiers: KeyModifiers::CONTROL,
-                ..
-            }) => {
-                exit(-1);
+    let guard = TerminalGuard::new()?;
+    let mut release_timeout = (!guard.reports_key_releases())
+        .then(|| ReleaseTimeout::new(time::Duration::from_millis(options.key_hold_ms)));
+
+    #[cfg(feature = "gamepad")]
+    let mut gamepad = if options.gamepad {
+        Some(gamepad::Gamepad::new(options.gamepad_map.clone())?)
+    } else {
+        None
+    };
+
+    let mut recorder = options
+        .record
+        .as_deref()
+        .map(replay::Recorder::create)
+        .transpose()?;
+    let mut player = options
+        .replay
+        .as_deref()
+        .map(replay::Player::open)
+        .transpose()?;
+
+    let mut keyboard_state = KeyboardState::new();
+    let mut misaligned = HashSet::new();
+    let mut unknown = HashSet::new();
+    let mut turbo = false;
+    let mut showing_beep = false;
+    let min_beep = time::Duration::from_millis(options.min_beep_ms);
+    let beep_started = Rc::new(Cell::new(None));
+    let started = beep_started.clone();
+    chip8.set_on_sound(Box::new(move |on| {
+        if on {
+            started.set(Some(time::Instant::now()));
+        }
+    }));
+    let mut slowdown: u64 = 1;
+    let mut speedup: u64 = 1;
+    let mut paused = false;
+    let mut focused = true;
+    let mut frame_advance = false;
+    let mut real_frames: u64 = 0;
+    let mut gif = options
+        .record_gif
+        .as_deref()
+        .map(|path| output::GifRecorder::new(path, options.scale as usize, options.max_gif_frames));
+    let mut cycle: u64 = 0;
+    let mut instructions = RateCounter::new();
+    let mut frames = RateCounter::new();
+    let cycles_per_frame = (speed / FRAMES_PER_SECOND).max(1);
+    let frame_duration = time::Duration::from_secs(1) / FRAMES_PER_SECOND as u32;
+    let mut next_frame = time::Instant::now();
+    let deadline = options
+        .duration
+        .map(|seconds| next_frame + time::Duration::from_secs(seconds));
+    let mut draw_credit = 0;
+    let mut editor: Option<MemoryEditor> = None;
+    let mut renderer = render::renderer(&options);
+    let mut force_redraw = false;
+    let mut rewind = Rewind::new(options.rewind_depth);
+    let mut debug_view = DebugView::default();
+    let mut terminal_input = TerminalInput;
+    #[cfg(feature = "watch")]
+    let mut watcher = if options.watch_rom {
+        Some(watch::RomWatcher::new(&options.program)?)
+    } else {
+        None
+    };
+    let result = 'run: loop {
+        // Quitting between frames, so the last one is complete in screenshots and GIFs
+        if deadline.is_some_and(|deadline| time::Instant::now() >= deadline) {
+            break Ok(());
+        }
+
+        // The editor takes all the keys while open, so typing doesn't press keypad keys
+        let controls = match editor.as_mut() {
+            Some(open) => {
+                if let Some(code) = input::read_key(&time::Duration::ZERO)? {
+                    if !open.key(code, &mut chip8.memory) {
+                        editor = None;
+                    }
+                }
+
+                Vec::new()
             }
-            Event::Key(KeyEvent { code, kind, .. }) => {
-                for (i, &key) in KEYS.iter().enumerate() {
-                    if code == key {
-                        match kind {
-                            KeyEventKind::Press => {
-                                state.keys_pressed[i] = true;
-                                state.pressed_key = Some(i as u8);
-                            }
-                            KeyEventKind::Release => {
-                                state.keys_pressed[i] = false;
-                            }
-                            KeyEventKind::Repeat => {
-                                state.keys_pressed[i] = true;
+            None => update_keyboard_state(
+                &mut keyboard_state,
+                &mut terminal_input,
+                &options.keymap,
+                release_timeout.as_mut(),
+                &time::Duration::ZERO,
+            )?,
+        };
+
+        for control in controls {
+            match control {
+                Control::Quit => break 'run Ok(()),
+                Control::CycleMode => {
+                    chip8.set_mode(chip8.mode.next());
+                }
+                Control::Turbo(held) => turbo = held,
+                Control::SlowDown if speedup > 1 => speedup /= 2,
+                Control::SlowDown => slowdown = (slowdown * 2).min(MAX_SLOWDOWN),
+                Control::SpeedUp => slowdown = (slowdown / 2).max(1),
+                Control::SpeedPreset(index) => {
+                    if let Some(preset) = options.speed_presets.get(index) {
+                        (speedup, slowdown) = (preset.speedup, preset.slowdown);
+                    }
+                }
+                Control::Pause => paused = !paused,
+                Control::FrameAdvance => {
+                    paused = true;
+                    frame_advance = true;
+                }
+                Control::Rewind => {
+                    paused = true;
+                    if let Some(snapshot) = rewind.rewind() {
+                        chip8.restore(snapshot);
+                        force_redraw = true;
+                    }
+                }
+                Control::Focus(gained) => {
+                    focused = gained;
+                    draw_status(if focused { "" } else { "Paused, lost focus" })?;
+                }
+                Control::EditMemory if options.debug => {
+                    keyboard_state = KeyboardState::new();
+                    editor = Some(MemoryEditor::default());
+                }
+                Control::EditMemory => {}
+                Control::NextProgram if programs.len() > 1 => {
+                    current = (current + 1) % programs.len();
+                    chip8.reset();
+                    chip8.load(&programs[current])?;
+                }
+                Control::NextProgram => {}
+                Control::NextPane => debug_view.next_pane(),
+                Control::Scroll(rows) => debug_view.scroll(rows),
+                Control::Paste(text) => match rom::parse_hex(&text) {
+                    Ok(program) => {
+                        chip8.reset();
+                        match chip8.load(&program) {
+                            Ok(()) => {
+                                draw_status(&format!("Loaded {} pasted bytes", program.len()))?
                             }
+                            Err(error) => draw_status(&format!("Ignored paste: {}", error))?,
+                        }
+                    }
+                    Err(error) => draw_status(&format!("Ignored paste: {}", error))?,
+                },
+                Control::Resize => {
+                    stdout().queue(terminal::Clear(terminal::ClearType::All))?;
+                    force_redraw = true;
+                }
+            }
+        }
+
+        #[cfg(feature = "watch")]
+        if let Some(index) = watcher.as_mut().and_then(watch::RomWatcher::changed) {
+            // Keep running the old copy if the new one can't be read or loaded
+            let location = &options.program[index];
+            match rom::read_program(location, options.entry.as_deref(), program_limit) {
+                Ok(program) => {
+                    programs[index] = program;
+                    if index == current {
+                        chip8.reset();
+                        if let Err(error) = chip8.load(&programs[current]) {
+                            draw_status(&format!("error: reloading {}: {}", location, error))?;
+                        } else {
+                            draw_status(&format!("Reloaded {}", location))?;
                         }
                     }
                 }
+                Err(error) => draw_status(&format!("error: reloading {}: {}", location, error))?,
+            }
+        }
+
+        let mut input = keyboard_state.clone();
+
+        #[cfg(feature = "gamepad")]
+        if let Some(gamepad) = gamepad.as_mut() {
+            gamepad.update();
+            gamepad.merge_into(&mut input);
+        }
+
+        // Run a frame's worth of instructions, then draw and tick the timers once like the
+        // display refresh on real hardware. Turbo runs several frames, timers included, so
+        // games fast forward at their usual pace.
+        // Slow motion only emulates every `slowdown`th frame, speed presets above 1x run
+        // `speedup` frames at once like turbo.
+        real_frames += 1;
+        let emulated_frames = if !focused {
+            0
+        } else if paused {
+            frame_advance as u64
+        } else if !real_frames.is_multiple_of(slowdown) {
+            0
+        } else if turbo {
+            TURBO_FRAMES
+        } else {
+            speedup
+        };
+        frame_advance = false;
+
+        // A press stays latched until a frame has run with it, so FX0A sees taps that were
+        // released between polls
+        if emulated_frames > 0 {
+            keyboard_state.pressed_key = None;
+        }
+
+        for frame in 0..emulated_frames {
+            rewind.record(&chip8);
+            if frame > 0 {
+                chip8.tick_timers();
+                chip8.vblank();
+            }
+
+            // Stops early while waiting for a key or vblank, so the loop sleeps rather than spins
+            let ran = chip8.run_frame(cycles_per_frame, |chip8| -> Result<(), Error> {
+                if let Some(player) = player.as_mut() {
+                    player.apply(cycle, &mut input);
+                }
+
+                if let Some(recorder) = recorder.as_mut() {
+                    recorder.record(cycle, &input)?;
+                }
+
+                let address = chip8.program_counter;
+                chip8.step(&input)?;
+
+                if let Some(warning) = chip8.take_mode_warning() {
+                    draw_status(&format!("warning: {}", warning))?;
+                }
+
+                if options.warn_misaligned
+                    && chip8.program_counter % 2 == 1
+                    && misaligned.insert(chip8.program_counter)
+                {
+                    draw_status(&format!(
+                        "warning: {:#06x} moved the PC to odd address {:#06x}",
+                        address, chip8.program_counter
+                    ))?;
+                }
+
+                if let Some((opcode, address)) = chip8.take_unknown_opcode() {
+                    if options.trace_unknown && unknown.insert(opcode) {
+                        draw_status(&format!(
+                            "warning: skipped unknown opcode {:04X} at {:#06x}",
+                            opcode, address
+                        ))?;
+                    }
+                }
+
+                cycle += 1;
+                instructions.tick();
+                Ok(())
+            });
+            if let Err(error) = ran {
+                break 'run Err(error);
             }
-            _ => {}
         }
+
+        // Drawing earns credit at --fps and spends a frame's worth per draw, so slow
+        // terminals skip frames evenly and the skipped changes coalesce into the next draw.
+        draw_credit += options.fps;
+        if draw_credit >= FRAMES_PER_SECOND {
+            let animating = renderer.animating(&chip8.display);
+            if chip8.take_redraw() || animating || std::mem::take(&mut force_redraw) {
+                renderer.render(&chip8.display)?;
+                if let Some(gif) = gif.as_mut() {
+                    gif.capture(real_frames, &chip8.display);
+                }
+                frames.tick();
+                draw_credit -= FRAMES_PER_SECOND;
+            } else {
+                draw_credit = FRAMES_PER_SECOND;
+            }
+        }
+
+        if options.debug {
+            let speed = if paused {
+                "paused".to_string()
+            } else if turbo {
+                format!("{}x", TURBO_FRAMES)
+            } else if slowdown > 1 {
+                format!("1/{}x", slowdown)
+            } else {
+                format!("{}x", speedup)
+            };
+            let rates = format!(
+                "Speed: {}  IPS: {:.0}  FPS: {:.0}",
+                speed,
+                instructions.rate(),
+                frames.rate()
+            );
+            renderer.render_debug(
+                &chip8,
+                &input,
+                &DebugOverlay {
+                    program: &options.program[current],
+                    rates: &rates,
+                    editor: editor.as_ref(),
+                    view: &debug_view,
+                },
+            )?;
+        }
+
+        // Sampled before the timers tick so a sound timer of 1 still beeps for a frame, then
+        // held for at least --min-beep-ms from when the beep started.
+        let beeping = chip8.is_beeping()
+            || beep_started
+                .get()
+                .is_some_and(|started| started.elapsed() < min_beep);
+
+        if emulated_frames > 0 {
+            chip8.tick_timers();
+            chip8.vblank();
+        }
+
+        if options.visual_beep && beeping != showing_beep {
+            draw_beep(beeping)?;
+            showing_beep = beeping;
+        }
+
+        // A fixed timestep only counts instructions, so there's no wall clock to keep up with
+        if options.fixed_timestep {
+            continue;
+        }
+
+        next_frame += frame_duration;
+        let now = time::Instant::now();
+        if next_frame > now {
+            thread::sleep(next_frame - now);
+        } else {
+            // Running behind, don't try to catch up with a burst of frames.
+            next_frame = now;
+        }
+    };
+
+    if let Some(recorder) = recorder.as_mut() {
+        recorder.flush()?;
     }
 
-    Ok(())
+    if let Some(path) = options.rpl_file.as_deref() {
+        std::fs::write(path, chip8.rpl_flags)?;
+    }
+
+    if let Some(path) = options.dump_memory.as_deref() {
+        std::fs::write(path, &chip8.memory)?;
+    }
+
+    if let Some(path) = options.screenshot.as_deref() {
+        output::write_pbm(path, &chip8.display, options.scale as usize)?;
+    }
+
+    if let Some(gif) = gif {
+        gif.save(FRAMES_PER_SECOND)?;
+    }
+
+    drop(guard);
+    if let Some(profile) = chip8.profile() {
+        info::print_profile(profile);
+    }
+
+    if let Some(covered) = chip8.coverage() {
+        info::print_coverage(&covered);
+    }
+
+    result
 }
@@ -0,0 +1,198 @@
+use std::io::{BufRead, Error, Write};
+
+use chip8::{decode, Chip8, KeyboardState};
+
+use crate::cli::parse_number;
+
+const HELP: &str = "\
+m <addr> [count]          examine memory
+m <addr> = <byte> ...     write memory
+r                         show registers
+r <V0-VF|I|PC> = <value>  set a register
+d [addr] [count]          disassemble, from the PC by default
+s [count]                 step
+q                         quit";
+
+/// A machine-language monitor: examine and change the machine between single steps,
+/// reading commands line by line from `input`.
+pub fn run(chip8: &mut Chip8, input: impl BufRead, mut output: impl Write) -> Result<(), Error> {
+    writeln!(output, "{}", HELP)?;
+    write!(output, "> ")?;
+    output.flush()?;
+    for line in input.lines() {
+        let line = line?;
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["q"] => return Ok(()),
+            [] => {}
+            words => {
+                if let Err(message) = command(chip8, words, &mut output)? {
+                    writeln!(output, "{}", message)?;
+                }
+            }
+        }
+
+        write!(output, "> ")?;
+        output.flush()?;
+    }
+
+    Ok(())
+}
+
+/// Runs one command, with the inner error describing a bad command.
+fn command(
+    chip8: &mut Chip8,
+    words: &[&str],
+    output: &mut impl Write,
+) -> Result<Result<(), String>, Error> {
+    let address = |word: &str| -> Result<usize, String> {
+        let address = parse_number(word)?;
+        if address >= chip8.memory.len() {
+            return Err(format!("{:#06x} is outside of memory", address));
+        }
+
+        Ok(address)
+    };
+
+    match words {
+        ["m", start, "=", bytes @ ..] => {
+            let start = match address(start) {
+                Ok(start) => start,
+                Err(message) => return Ok(Err(message)),
+            };
+            for (i, byte) in bytes.iter().enumerate() {
+                let value = match parse_number(byte) {
+                    Ok(value) if value <= 0xFF => value as u8,
+                    Ok(value) => return Ok(Err(format!("{:#x} isn't a byte", value))),
+                    Err(message) => return Ok(Err(message)),
+                };
+                match chip8.memory.get_mut(start + i) {
+                    Some(cell) => *cell = value,
+                    None => return Ok(Err("ran past the end of memory".into())),
+                }
+            }
+        }
+        ["m", start, rest @ ..] => {
+            let (start, count) = match (address(start), count(rest, 16)) {
+                (Ok(start), Ok(count)) => (start, count),
+                (Err(message), _) | (_, Err(message)) => return Ok(Err(message)),
+            };
+            let end = (start + count).min(chip8.memory.len());
+            for (row, bytes) in chip8.memory[start..end].chunks(16).enumerate() {
+                let hex: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+                writeln!(output, "{:04X}: {}", start + row * 16, hex.join(" "))?;
+            }
+        }
+        ["r"] => {
+            for (i, value) in chip8.registers.iter().enumerate() {
+                write!(output, "V{:X}={:02X} ", i, value)?;
+            }
+            writeln!(output)?;
+            writeln!(
+                output,
+                "I={:04X} PC={:04X} SP={} DT={} ST={}",
+                chip8.index_register,
+                chip8.program_counter,
+                chip8.stack.len(),
+                chip8.delay_timer,
+                chip8.sound_timer
+            )?;
+        }
+        ["r", register, "=", value] => {
+            let value = match parse_number(value) {
+                Ok(value) => value,
+                Err(message) => return Ok(Err(message)),
+            };
+            let register = register.to_ascii_uppercase();
+            match register.as_str() {
+                "I" if value <= 0xFFFF => chip8.index_register = value as u16,
+                "PC" if value < chip8.memory.len() => chip8.program_counter = value,
+                _ => match register
+                    .strip_prefix('V')
+                    .and_then(|x| usize::from_str_radix(x, 16).ok())
+                {
+                    Some(x) if x < 16 && value <= 0xFF => chip8.registers[x] = value as u8,
+                    _ => return Ok(Err(format!("can't set {} to {:#x}", register, value))),
+                },
+            }
+        }
+        ["d", rest @ ..] => {
+            let start = match rest.first().map(|word| address(word)) {
+                Some(Ok(start)) => start,
+                Some(Err(message)) => return Ok(Err(message)),
+                None => chip8.program_counter,
+            };
+            let count = match count(rest.get(1..).unwrap_or_default(), 10) {
+                Ok(count) => count,
+                Err(message) => return Ok(Err(message)),
+            };
+            for address in (start..chip8.memory.len() - 1).step_by(2).take(count) {
+                let opcode = u16::from_be_bytes([chip8.memory[address], chip8.memory[address + 1]]);
+                let marker = if address == chip8.program_counter {
+                    '>'
+                } else {
+                    ' '
+                };
+                writeln!(
+                    output,
+                    "{}{:04X}: {:04X}  {}",
+                    marker,
+                    address,
+                    opcode,
+                    decode(opcode)
+                )?;
+            }
+        }
+        ["s", rest @ ..] => {
+            let count = match count(rest, 1) {
+                Ok(count) => count,
+                Err(message) => return Ok(Err(message)),
+            };
+            for _ in 0..count {
+                if let Err(error) = chip8.step(&KeyboardState::new()) {
+                    return Ok(Err(error.to_string()));
+                }
+                chip8.vblank();
+            }
+
+            return command(
+                chip8,
+                &["d", &chip8.program_counter.to_string(), "1"],
+                output,
+            );
+        }
+        _ => return Ok(Err(format!("unknown command, try:\n{}", HELP))),
+    }
+
+    Ok(Ok(()))
+}
+
+fn count(words: &[&str], default: usize) -> Result<usize, String> {
+    match words {
+        [] => Ok(default),
+        [count] => parse_number(count),
+        _ => Err("too many arguments".into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip8::Mode;
+
+    #[test]
+    fn pokes_steps_and_examines() {
+        let mut chip8 = Chip8::new(Mode::Chip8);
+        let mut output = Vec::new();
+        let input = "m 0x200 = 0x60 0x2A\nr V1 = 7\ns\nr\nd 0x200 1\nbogus\nq\nm 0x200\n";
+        run(&mut chip8, input.as_bytes(), &mut output).unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(chip8.registers[0], 0x2A);
+        assert_eq!(chip8.registers[1], 7);
+        assert!(output.contains("V0=2A V1=07"));
+        assert!(output.contains(" 0200: 602A  LD V0, 0x2A"));
+        assert!(output.contains("unknown command"));
+        assert!(!output.contains("0200: 60 2A"));
+    }
+}
@@ -0,0 +1,170 @@
+//! Play over TCP, with the emulator running headless on a server and a client showing the
+//! display and sending keys.
+//!
+//! The protocol is a stream of fixed size messages:
+//!
+//! * Server to client, whenever the display changes: `b'D'` followed by the 32 rows of
+//!   `Chip8::display_bits`, each a big endian `u64`.
+//! * Client to server, whenever a key changes: `[1, key]` for a press and `[0, key]` for a
+//!   release, with `key` from 0x0 to 0xF.
+//!
+//! Either side closing the connection ends the session.
+
+use std::{
+    io::{Error, ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::mpsc::{self, TryRecvError},
+    thread, time,
+};
+
+use chip8::{unpack_display, Chip8, KeyboardState};
+
+use crate::{
+    input::{update_keyboard_state, Control, ReleaseTimeout, TerminalInput},
+    keymap::Keymap,
+    render::Renderer,
+    FRAMES_PER_SECOND,
+};
+
+const DISPLAY_MESSAGE: u8 = b'D';
+
+fn send_display(stream: &mut TcpStream, chip8: &Chip8) -> Result<(), Error> {
+    let mut message = Vec::with_capacity(1 + 32 * 8);
+    message.push(DISPLAY_MESSAGE);
+    for row in chip8.display_bits() {
+        message.extend_from_slice(&row.to_be_bytes());
+    }
+
+    stream.write_all(&message)
+}
+
+/// Waits for a client on `address`, then runs the program for it until it disconnects.
+pub fn serve(chip8: &mut Chip8, address: &str, speed: u64) -> Result<(), Error> {
+    let listener = TcpListener::bind(address)?;
+    eprintln!("Waiting for a client on {}", listener.local_addr()?);
+    let (mut stream, client) = listener.accept()?;
+    eprintln!("Serving {}", client);
+
+    let (keys, key_events) = mpsc::channel();
+    let mut reader = stream.try_clone()?;
+    thread::spawn(move || {
+        let mut message = [0; 2];
+        while reader.read_exact(&mut message).is_ok() {
+            if keys.send((message[0] != 0, message[1] & 0xF)).is_err() {
+                break;
+            }
+        }
+    });
+
+    send_display(&mut stream, chip8)?;
+    let mut keyboard_state = KeyboardState::new();
+    let cycles_per_frame = (speed / FRAMES_PER_SECOND).max(1);
+    let frame_duration = time::Duration::from_secs(1) / FRAMES_PER_SECOND as u32;
+    let mut next_frame = time::Instant::now();
+    loop {
+        keyboard_state.pressed_key = None;
+        loop {
+            match key_events.try_recv() {
+                Ok((down, key)) => {
+                    keyboard_state.keys_pressed[key as usize] = down;
+                    if down {
+                        keyboard_state.pressed_key = Some(key);
+                    }
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return Ok(()),
+            }
+        }
+
+        chip8.run_cycles(cycles_per_frame as usize, &keyboard_state)?;
+
+        if chip8.take_redraw() {
+            send_display(&mut stream, chip8)?;
+        }
+
+        chip8.tick_timers();
+        chip8.vblank();
+
+        next_frame += frame_duration;
+        let now = time::Instant::now();
+        if next_frame > now {
+            thread::sleep(next_frame - now);
+        } else {
+            next_frame = now;
+        }
+    }
+}
+
+/// Connects to a server, showing its display and sending it keypad changes.
+pub fn connect(
+    address: &str,
+    keymap: &Keymap,
+    mut release_timeout: Option<ReleaseTimeout>,
+    renderer: &mut dyn Renderer,
+) -> Result<(), Error> {
+    let mut stream = TcpStream::connect(address)?;
+    let (displays, display_events) = mpsc::channel();
+    let mut reader = stream.try_clone()?;
+    thread::spawn(move || {
+        let mut message = [0; 1 + 32 * 8];
+        while reader.read_exact(&mut message).is_ok() && message[0] == DISPLAY_MESSAGE {
+            let mut bits = [0; 32];
+            for (row, bytes) in bits.iter_mut().zip(message[1..].chunks_exact(8)) {
+                *row = u64::from_be_bytes(bytes.try_into().unwrap());
+            }
+
+            if displays.send(bits).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut keyboard_state = KeyboardState::new();
+    let mut sent = [false; 16];
+    let frame_duration = time::Duration::from_secs(1) / FRAMES_PER_SECOND as u32;
+    loop {
+        let controls = update_keyboard_state(
+            &mut keyboard_state,
+            &mut TerminalInput,
+            keymap,
+            release_timeout.as_mut(),
+            &frame_duration,
+        )?;
+        if controls
+            .iter()
+            .any(|control| matches!(control, Control::Quit))
+        {
+            return Ok(());
+        }
+
+        for (key, (&pressed, sent)) in keyboard_state
+            .keys_pressed
+            .iter()
+            .zip(sent.iter_mut())
+            .enumerate()
+        {
+            if pressed != *sent {
+                stream.write_all(&[pressed as u8, key as u8])?;
+                *sent = pressed;
+            }
+        }
+
+        let mut latest = None;
+        loop {
+            match display_events.try_recv() {
+                Ok(bits) => latest = Some(bits),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    return Err(Error::new(
+                        ErrorKind::ConnectionAborted,
+                        "the server closed the connection",
+                    ))
+                }
+            }
+        }
+
+        if let Some(bits) = latest {
+            renderer.render(&unpack_display(&bits))?;
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Error},
+};
+
+use chip8::Chip8;
+
+use crate::romdb::rom_hash;
+
+/// Grayscale colors for the pixel values, off and the three plane combinations.
+const GIF_PALETTE: [u8; 12] = [
+    0, 0, 0, 0xff, 0xff, 0xff, 0xaa, 0xaa, 0xaa, 0x55, 0x55, 0x55,
+];
+
+/// Nearest neighbor upscales the display by `scale`, one byte per pixel, row by row.
+pub fn scale_display(display: &[[u8; 64]; 32], scale: usize) -> Vec<Vec<u8>> {
+    display
+        .iter()
+        .flat_map(|row| {
+            let scaled: Vec<u8> = row
+                .iter()
+                .flat_map(|&pixel| std::iter::repeat_n(pixel, scale))
+                .collect();
+            std::iter::repeat_n(scaled, scale)
+        })
+        .collect()
+}
+
+/// The SHA-1 of the display packed one bit per pixel, as `Chip8::display_bits` big endian.
+pub fn display_hash(bits: &[u64; 32]) -> String {
+    let bytes: Vec<u8> = bits.iter().flat_map(|row| row.to_be_bytes()).collect();
+    rom_hash(&bytes)
+}
+
+/// The machine's state as a single line of JSON.
+pub fn state_json(chip8: &Chip8) -> Result<String, Error> {
+    Ok(serde_json::to_string(&chip8.state())?)
+}
+
+/// Writes the display as a plain PBM image, upscaled by `scale`.
+pub fn write_pbm(path: &str, display: &[[u8; 64]; 32], scale: usize) -> Result<(), Error> {
+    let rows = scale_display(display, scale);
+    let mut pbm = format!("P1\n{} {}\n", 64 * scale, 32 * scale);
+    for row in rows {
+        let pixels: Vec<&str> = row
+            .iter()
+            .map(|&pixel| if pixel == 0 { "0" } else { "1" })
+            .collect();
+        pbm.push_str(&pixels.join(" "));
+        pbm.push('\n');
+    }
+
+    fs::write(path, pbm)
+}
+
+/// How long to show the frame captured at `frame` until the one at `next`, in the GIF's
+/// hundredths of a second. Rounding each timestamp rather than each gap keeps the total in step
+/// at frame rates that don't divide 100. Browsers slow anything under 2 down to about 10, so
+/// that's the shortest.
+fn gif_delay(frame: u64, next: u64, fps: u64) -> u16 {
+    let centiseconds = |frame: u64| (frame * 100 + fps / 2) / fps;
+    (centiseconds(next) - centiseconds(frame)).clamp(2, u16::MAX as u64) as u16
+}
+
+/// Collects rendered frames in memory to encode as an animated GIF on exit.
+pub struct GifRecorder {
+    path: String,
+    scale: usize,
+    max_frames: usize,
+    /// Each captured display with the emulation frame it was rendered on.
+    frames: Vec<(u64, [[u8; 64]; 32])>,
+}
+
+impl GifRecorder {
+    pub fn new(path: &str, scale: usize, max_frames: usize) -> Self {
+        Self {
+            path: path.to_string(),
+            scale,
+            max_frames,
+            frames: Vec::new(),
+        }
+    }
+
+    /// Captures the display rendered on `frame`, dropping it once the cap is reached.
+    pub fn capture(&mut self, frame: u64, display: &[[u8; 64]; 32]) {
+        if self.frames.len() < self.max_frames {
+            self.frames.push((frame, *display));
+        }
+    }
+
+    /// Encodes the captured frames, each shown until the next one was rendered at `fps`.
+    pub fn save(&self, fps: u64) -> Result<(), Error> {
+        let (width, height) = ((64 * self.scale) as u16, (32 * self.scale) as u16);
+        let file = BufWriter::new(File::create(&self.path)?);
+        let mut encoder =
+            gif::Encoder::new(file, width, height, &GIF_PALETTE).map_err(Error::other)?;
+        encoder
+            .set_repeat(gif::Repeat::Infinite)
+            .map_err(Error::other)?;
+
+        for (i, (frame, display)) in self.frames.iter().enumerate() {
+            let next = self.frames.get(i + 1).map_or(frame + 1, |(next, _)| *next);
+            let pixels: Vec<u8> = scale_display(display, self.scale)
+                .into_iter()
+                .flatten()
+                .map(|pixel| pixel & 3)
+                .collect();
+            let mut gif_frame = gif::Frame::from_indexed_pixels(width, height, pixels, None);
+            gif_frame.delay = gif_delay(*frame, next, fps);
+            encoder.write_frame(&gif_frame).map_err(Error::other)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn state_json_packs_the_display() {
+        let mut chip8 = Chip8::new(chip8::Mode::Chip8);
+        chip8.registers[0xF] = 1;
+        chip8.display[1][0] = 1;
+        let state: serde_json::Value = serde_json::from_str(&state_json(&chip8).unwrap()).unwrap();
+        assert_eq!(state["registers"][15], 1);
+        assert_eq!(state["program_counter"], 0x200);
+        assert_eq!(state["mode"], "Chip8");
+        assert_eq!(state["display"][1], 1u64 << 63);
+    }
+
+    #[test]
+    fn gif_delays_follow_the_frame_rate() {
+        let delays: Vec<u16> = [0, 3, 6, 9, 12]
+            .windows(2)
+            .map(|w| gif_delay(w[0], w[1], 60))
+            .collect();
+        assert_eq!(delays, [5, 5, 5, 5]);
+        assert_eq!(gif_delay(0, 2, 60), 3);
+        assert_eq!(gif_delay(2, 3, 60), 2);
+        assert_eq!(gif_delay(7, 8, 10), 10);
+    }
+
+    #[test]
+    fn scaling_repeats_pixels_and_rows() {
+        let mut display = [[0; 64]; 32];
+        display[0][1] = 1;
+        let rows = scale_display(&display, 2);
+        assert_eq!((rows.len(), rows[0].len()), (64, 128));
+        assert_eq!(rows[0][..4], [0, 0, 1, 1]);
+        assert_eq!(rows[1], rows[0]);
+        assert!(rows[2].iter().all(|&pixel| pixel == 0));
+    }
+
+    #[test]
+    fn display_hash_covers_every_pixel() {
+        let mut bits = [0; 32];
+        let blank = display_hash(&bits);
+        assert_eq!(blank, rom_hash(&[0; 256]));
+        bits[31] = 1;
+        assert_ne!(display_hash(&bits), blank);
+    }
+}
@@ -0,0 +1,70 @@
+//! Phosphor persistence for `--ghosting`. Pixels that turn off fade out over a few frames like
+//! a CRT, which hides the flicker of XOR drawn sprites.
+
+/// Shades for fading pixels, dimmest first.
+const RAMP: [char; 3] = ['░', '▒', '▓'];
+
+/// The glow of a lit pixel, one more than the fading shades.
+const LIT: u8 = RAMP.len() as u8 + 1;
+
+pub struct Phosphor {
+    glow: [[u8; 64]; 32],
+}
+
+impl Default for Phosphor {
+    fn default() -> Self {
+        Self {
+            glow: [[0; 64]; 32],
+        }
+    }
+}
+
+impl Phosphor {
+    /// Relights the lit pixels and fades the rest a step, returning whether anything was still
+    /// fading and so needs to be redrawn.
+    pub fn fade(&mut self, display: &[[u8; 64]; 32]) -> bool {
+        let mut fading = false;
+        for (glow_row, row) in self.glow.iter_mut().zip(display) {
+            for (glow, &pixel) in glow_row.iter_mut().zip(row) {
+                if pixel != 0 {
+                    *glow = LIT;
+                } else if *glow > 0 {
+                    *glow -= 1;
+                    fading = true;
+                }
+            }
+        }
+
+        fading
+    }
+
+    /// The shade to draw an unlit pixel with while it fades.
+    pub fn shade(&self, x: usize, y: usize) -> Option<char> {
+        match self.glow[y][x] {
+            0 | LIT => None,
+            glow => Some(RAMP[glow as usize - 1]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unlit_pixels_fade_through_the_ramp() {
+        let mut phosphor = Phosphor::default();
+        let mut display = [[0; 64]; 32];
+        display[1][2] = 1;
+        assert!(!phosphor.fade(&display));
+        assert_eq!(phosphor.shade(2, 1), None);
+
+        display[1][2] = 0;
+        let mut shades = Vec::new();
+        while phosphor.fade(&display) {
+            shades.extend(phosphor.shade(2, 1));
+        }
+        assert_eq!(shades, ['▓', '▒', '░']);
+        assert_eq!(phosphor.shade(2, 1), None);
+    }
+}
@@ -0,0 +1,369 @@
+//! Drawing the machine. Everything that shows the display goes through `Renderer`, so other
+//! outputs can stand in for the terminal. Blocks of characters work anywhere, terminals with
+//! the kitty graphics protocol can show the display as an image instead.
+
+use std::io::{stdout, Error, Write};
+
+use chip8::{Chip8, KeyboardState};
+use crossterm::{
+    cursor,
+    style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor},
+    terminal, QueueableCommand,
+};
+
+use crate::{
+    cli::CliOptions, debug::DebugView, editor::MemoryEditor, output::scale_display,
+    phosphor::Phosphor,
+};
+
+/// How --render-mode draws the display.
+#[derive(Clone, Copy, Debug, Default, clap::ValueEnum)]
+pub enum RenderMode {
+    /// Characters and colors, which work in any terminal.
+    #[default]
+    Blocks,
+    /// An image through the kitty graphics protocol, for kitty, WezTerm and Ghostty. Other
+    /// terminals fall back to blocks.
+    Kitty,
+}
+
+/// The renderer for --render-mode, falling back to blocks when the terminal can't do better.
+pub fn renderer(options: &CliOptions) -> Box<dyn Renderer> {
+    match options.render_mode {
+        RenderMode::Kitty if supports_kitty_graphics() => Box::new(KittyRenderer::new(options)),
+        RenderMode::Kitty => {
+            log::warn!("the terminal doesn't look like it supports kitty graphics, using blocks");
+            Box::new(TerminalRenderer::new(options))
+        }
+        RenderMode::Blocks => Box::new(TerminalRenderer::new(options)),
+    }
+}
+
+/// There's no portable way to ask without reading the reply from the input stream, so this
+/// goes by the environment the terminals set.
+fn supports_kitty_graphics() -> bool {
+    let var = |name| std::env::var(name).unwrap_or_default();
+    std::env::var_os("KITTY_WINDOW_ID").is_some()
+        || var("TERM").contains("kitty")
+        || var("TERM").contains("ghostty")
+        || matches!(var("TERM_PROGRAM").as_str(), "WezTerm" | "ghostty")
+}
+
+/// What the debug overlay shows beyond the machine itself.
+pub struct DebugOverlay<'a> {
+    pub program: &'a str,
+    /// Speed, instructions and frames per second, already formatted.
+    pub rates: &'a str,
+    pub editor: Option<&'a MemoryEditor>,
+    pub view: &'a DebugView,
+}
+
+pub trait Renderer {
+    /// Called once per drawn frame, returning whether the picture is still changing when the
+    /// display isn't, e.g. pixels fading out.
+    fn animating(&mut self, _display: &[[u8; 64]; 32]) -> bool {
+        false
+    }
+
+    fn render(&mut self, display: &[[u8; 64]; 32]) -> Result<(), Error>;
+
+    fn render_debug(
+        &mut self,
+        machine: &Chip8,
+        keyboard: &KeyboardState,
+        overlay: &DebugOverlay,
+    ) -> Result<(), Error>;
+}
+
+/// Draws with characters and colors through crossterm.
+pub struct TerminalRenderer {
+    pixel_on: char,
+    pixel_off: char,
+    palette: [Color; 4],
+    /// Mirrored `(horizontally, vertically)`.
+    flip: (bool, bool),
+    phosphor: Option<Phosphor>,
+}
+
+impl TerminalRenderer {
+    pub fn new(options: &CliOptions) -> Self {
+        Self {
+            pixel_on: options.pixel_on,
+            pixel_off: options.pixel_off,
+            palette: options.palette(),
+            flip: options.flip(),
+            phosphor: options.ghosting.then(Phosphor::default),
+        }
+    }
+}
+
+impl Renderer for TerminalRenderer {
+    fn animating(&mut self, display: &[[u8; 64]; 32]) -> bool {
+        self.phosphor
+            .as_mut()
+            .is_some_and(|phosphor| phosphor.fade(display))
+    }
+
+    fn render(&mut self, display: &[[u8; 64]; 32]) -> Result<(), Error> {
+        let (flip_h, flip_v) = self.flip;
+        // Clipped to the terminal, so a small one doesn't wrap the rows into garbage
+        let (columns, rows) = terminal::size()?;
+        let mut stdout = stdout();
+        stdout.queue(SetBackgroundColor(self.palette[0]))?;
+        for i in 0..display.len().min(rows as usize) {
+            let y = if flip_v { display.len() - 1 - i } else { i };
+            stdout.queue(cursor::MoveTo(0, i as u16))?;
+            for j in 0..display[y].len().min(columns as usize) {
+                let x = if flip_h { display[y].len() - 1 - j } else { j };
+                let pixel = display[y][x];
+                if pixel == 0 {
+                    let shade = self
+                        .phosphor
+                        .as_ref()
+                        .and_then(|phosphor| phosphor.shade(x, y));
+                    stdout
+                        .queue(SetForegroundColor(self.palette[1]))?
+                        .queue(Print(shade.unwrap_or(self.pixel_off)))?;
+                } else {
+                    stdout
+                        .queue(SetForegroundColor(self.palette[(pixel & 3) as usize]))?
+                        .queue(Print(self.pixel_on))?;
+                }
+            }
+        }
+
+        stdout.queue(ResetColor)?;
+        if (columns as usize) < display[0].len() || (rows as usize) < display.len() {
+            let warning = format!(
+                "Terminal is {}x{}, the display needs {}x{}",
+                columns,
+                rows,
+                display[0].len(),
+                display.len()
+            );
+            stdout
+                .queue(cursor::MoveTo(0, rows.saturating_sub(1)))?
+                .queue(Print(&warning[..warning.len().min(columns as usize)]))?;
+        }
+
+        stdout.flush()?;
+
+        Ok(())
+    }
+
+    fn render_debug(
+        &mut self,
+        machine: &Chip8,
+        keyboard: &KeyboardState,
+        overlay: &DebugOverlay,
+    ) -> Result<(), Error> {
+        const DEBUG_COLUMN: u16 = 66;
+        /// The narrowest the overlay can be beside the display before it moves under it.
+        const MIN_WIDTH: u16 = 32;
+        /// Under the display and its status line.
+        const BELOW_ROW: u16 = 34;
+
+        let (columns, rows) = terminal::size()?;
+        let (column, top, width, height) = if columns >= DEBUG_COLUMN + MIN_WIDTH {
+            (DEBUG_COLUMN, 0, columns - DEBUG_COLUMN, rows)
+        } else if rows > BELOW_ROW {
+            (0, BELOW_ROW, columns, rows - BELOW_ROW)
+        } else {
+            return Ok(());
+        };
+
+        let mut stdout = stdout();
+        let mut info = vec![
+            format!("ROM: {}", overlay.program),
+            overlay.rates.to_string(),
+            format!("Key: {:?}", keyboard.pressed_key),
+            format!(
+                "Pressed: {:?}",
+                keyboard
+                    .keys_pressed
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, &v)| v)
+                    .map(|(i, _)| format!("{:#x}", i))
+                    .collect::<Vec<_>>()
+            ),
+        ];
+        info.extend(overlay.editor.map_or_else(
+            || ["i: poke memory".to_string(), String::new()],
+            |editor| editor.lines(&machine.memory),
+        ));
+        info.push(overlay.view.title());
+        let pane_height = (height as usize).saturating_sub(info.len());
+        info.extend(overlay.view.lines(machine, pane_height));
+        // Blank the rest, so nothing is left over from a longer pane
+        info.resize(height as usize, String::new());
+
+        for (i, line) in info.iter().enumerate() {
+            let line: String = line.chars().take(width as usize).collect();
+            stdout
+                .queue(cursor::MoveTo(column, top + i as u16))?
+                .queue(Print(line))?
+                .queue(terminal::Clear(terminal::ClearType::UntilNewLine))?;
+        }
+
+        stdout.flush()?;
+
+        Ok(())
+    }
+}
+
+/// Draws the display as an image with the kitty graphics protocol, upscaled by --scale and
+/// stretched over the same 64x32 cells blocks would use. The debug overlay is still text.
+pub struct KittyRenderer {
+    text: TerminalRenderer,
+    colors: [[u8; 3]; 4],
+    flip: (bool, bool),
+    scale: usize,
+}
+
+impl KittyRenderer {
+    pub fn new(options: &CliOptions) -> Self {
+        let palette = options.palette();
+        Self {
+            text: TerminalRenderer::new(options),
+            colors: [
+                rgb(palette[0], [0x00; 3]),
+                rgb(palette[1], [0xff; 3]),
+                rgb(palette[2], [0xaa; 3]),
+                rgb(palette[3], [0x55; 3]),
+            ],
+            flip: options.flip(),
+            scale: options.scale as usize,
+        }
+    }
+}
+
+impl Renderer for KittyRenderer {
+    fn render(&mut self, display: &[[u8; 64]; 32]) -> Result<(), Error> {
+        let (columns, rows) = terminal::size()?;
+        if columns < 64 || rows < 32 {
+            // Blocks clip and explain what size is needed
+            return self.text.render(display);
+        }
+
+        let (flip_h, flip_v) = self.flip;
+        let mut flipped = *display;
+        if flip_v {
+            flipped.reverse();
+        }
+        if flip_h {
+            flipped.iter_mut().for_each(|row| row.reverse());
+        }
+
+        let rgb: Vec<u8> = scale_display(&flipped, self.scale)
+            .into_iter()
+            .flatten()
+            .flat_map(|pixel| self.colors[(pixel & 3) as usize])
+            .collect();
+        let mut stdout = stdout();
+        stdout
+            .queue(cursor::MoveTo(0, 0))?
+            .queue(Print(kitty_image(&rgb, 64 * self.scale, 32 * self.scale)))?;
+        stdout.flush()
+    }
+
+    fn render_debug(
+        &mut self,
+        machine: &Chip8,
+        keyboard: &KeyboardState,
+        overlay: &DebugOverlay,
+    ) -> Result<(), Error> {
+        self.text.render_debug(machine, keyboard, overlay)
+    }
+}
+
+/// Approximates a terminal color as RGB, using `default` for the terminal's own colors.
+fn rgb(color: Color, default: [u8; 3]) -> [u8; 3] {
+    match color {
+        Color::Rgb { r, g, b } => [r, g, b],
+        Color::Black => [0x00, 0x00, 0x00],
+        Color::DarkGrey => [0x80, 0x80, 0x80],
+        Color::Red => [0xff, 0x00, 0x00],
+        Color::DarkRed => [0x80, 0x00, 0x00],
+        Color::Green => [0x00, 0xff, 0x00],
+        Color::DarkGreen => [0x00, 0x80, 0x00],
+        Color::Yellow => [0xff, 0xff, 0x00],
+        Color::DarkYellow => [0x80, 0x80, 0x00],
+        Color::Blue => [0x00, 0x00, 0xff],
+        Color::DarkBlue => [0x00, 0x00, 0x80],
+        Color::Magenta => [0xff, 0x00, 0xff],
+        Color::DarkMagenta => [0x80, 0x00, 0x80],
+        Color::Cyan => [0x00, 0xff, 0xff],
+        Color::DarkCyan => [0x00, 0x80, 0x80],
+        Color::White => [0xff, 0xff, 0xff],
+        Color::Grey => [0xc0, 0xc0, 0xc0],
+        Color::Reset | Color::AnsiValue(_) => default,
+    }
+}
+
+/// The escape sequences that show `rgb` as a `width`x`height` image over 64x32 cells. The image
+/// and its placement keep the same ids, so each frame replaces the last instead of piling up.
+fn kitty_image(rgb: &[u8], width: usize, height: usize) -> String {
+    // Payloads are sent in chunks of at most 4096 base64 characters
+    const CHUNK: usize = 4096 / 4 * 3;
+
+    let chunks: Vec<&[u8]> = rgb.chunks(CHUNK).collect();
+    let mut commands = String::new();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let more = (i + 1 < chunks.len()) as u8;
+        if i == 0 {
+            commands.push_str(&format!(
+                "\x1b_Ga=T,f=24,s={},v={},c=64,r=32,i=1,p=1,C=1,q=2,m={};",
+                width, height, more
+            ));
+        } else {
+            commands.push_str(&format!("\x1b_Gm={};", more));
+        }
+        commands.push_str(&base64(chunk));
+        commands.push_str("\x1b\\");
+    }
+
+    commands
+}
+
+/// Standard, padded base64.
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for group in data.chunks(3) {
+        let bytes = [
+            group[0],
+            *group.get(1).unwrap_or(&0),
+            *group.get(2).unwrap_or(&0),
+        ];
+        let bits = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= group.len() {
+                encoded.push(ALPHABET[(bits >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kitty_images_are_chunked_base64() {
+        assert_eq!(base64(b"CHIP-8"), "Q0hJUC04");
+        assert_eq!(base64(b"XO"), "WE8=");
+
+        let commands = kitty_image(&[0xff; 3073], 1, 1);
+        let chunks: Vec<&str> = commands.split("\x1b\\").filter(|c| !c.is_empty()).collect();
+        assert_eq!(chunks.len(), 2);
+        assert!(chunks[0].starts_with("\x1b_Ga=T,f=24,s=1,v=1,") && chunks[0].contains("m=1;"));
+        assert_eq!(chunks[0].split(';').nth(1).unwrap().len(), 4096);
+        assert_eq!(chunks[1], "\x1b_Gm=0;/w==");
+    }
+}
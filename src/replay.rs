@@ -0,0 +1,220 @@
+//! Recording and playback of keypad input.
+//!
+//! Recordings are plain text, one line per change in input:
+//!
+//! ```text
+//! <cycle> <held keys as a 16-bit hex mask> <latched key press or ->
+//! ```
+//!
+//! Like live input, a press stays latched for `FX0A` until the line that clears it, which is
+//! usually the start of the next frame.
+//!
+//! Lines starting with `#` are ignored.
+
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Write},
+};
+
+use chip8::KeyboardState;
+
+const HEADER: &str = "# chip8 input recording: <cycle> <held mask> <pressed key>";
+
+#[derive(Clone, Copy, PartialEq)]
+struct Frame {
+    keys: u16,
+    pressed_key: Option<u8>,
+}
+
+impl Frame {
+    fn from_state(state: &KeyboardState) -> Self {
+        let keys = state
+            .keys_pressed
+            .iter()
+            .enumerate()
+            .filter(|(_, &held)| held)
+            .fold(0, |mask, (i, _)| mask | 1 << i);
+
+        Self {
+            keys,
+            pressed_key: state.pressed_key,
+        }
+    }
+
+    fn apply(&self, state: &mut KeyboardState) {
+        for (i, held) in state.keys_pressed.iter_mut().enumerate() {
+            *held = self.keys & (1 << i) != 0;
+        }
+
+        state.pressed_key = self.pressed_key;
+    }
+}
+
+/// Writes input to a file whenever it changes.
+pub struct Recorder {
+    writer: BufWriter<File>,
+    last: Frame,
+}
+
+impl Recorder {
+    pub fn create(path: &str) -> Result<Self, Error> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "{}", HEADER)?;
+
+        Ok(Self {
+            writer,
+            last: Frame {
+                keys: 0,
+                pressed_key: None,
+            },
+        })
+    }
+
+    pub fn record(&mut self, cycle: u64, state: &KeyboardState) -> Result<(), Error> {
+        let frame = Frame::from_state(state);
+        if frame == self.last {
+            return Ok(());
+        }
+
+        let pressed_key = match frame.pressed_key {
+            Some(key) => format!("{:x}", key),
+            None => "-".to_string(),
+        };
+
+        writeln!(self.writer, "{} {:04x} {}", cycle, frame.keys, pressed_key)?;
+        self.last = frame;
+
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.writer.flush()
+    }
+}
+
+/// Feeds recorded input back in place of the keyboard.
+pub struct Player {
+    frames: Vec<(u64, Frame)>,
+    next: usize,
+    current: Frame,
+}
+
+impl Player {
+    pub fn open(path: &str) -> Result<Self, Error> {
+        let reader = BufReader::new(File::open(path)?);
+        let mut frames = Vec::new();
+        for (number, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let invalid = || {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("{}:{}: malformed input line '{}'", path, number + 1, line),
+                )
+            };
+
+            let mut fields = line.split_whitespace();
+            let cycle = fields
+                .next()
+                .and_then(|f| f.parse().ok())
+                .ok_or_else(invalid)?;
+            let keys = fields
+                .next()
+                .and_then(|f| u16::from_str_radix(f, 16).ok())
+                .ok_or_else(invalid)?;
+            let pressed_key = match fields.next() {
+                Some("-") => None,
+                Some(f) => Some(
+                    u8::from_str_radix(f, 16)
+                        .ok()
+                        .filter(|&key| key < 16)
+                        .ok_or_else(invalid)?,
+                ),
+                None => return Err(invalid()),
+            };
+
+            frames.push((cycle, Frame { keys, pressed_key }));
+        }
+
+        Ok(Self {
+            frames,
+            next: 0,
+            current: Frame {
+                keys: 0,
+                pressed_key: None,
+            },
+        })
+    }
+
+    /// Overwrites `state` with the recorded input for `cycle`.
+    pub fn apply(&mut self, cycle: u64, state: &mut KeyboardState) {
+        while let Some(&(at, frame)) = self.frames.get(self.next) {
+            if at > cycle {
+                break;
+            }
+
+            self.current = frame;
+            self.next += 1;
+        }
+
+        self.current.apply(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chip8::{Chip8, Mode};
+
+    use super::*;
+
+    const CYCLES_PER_FRAME: u64 = 8;
+
+    /// Runs a frame per input, like the main loop, returning the key `FX0A` stored in V1.
+    fn run(
+        frames: &[KeyboardState],
+        mut on_cycle: impl FnMut(u64, &mut KeyboardState) -> Result<(), Error>,
+    ) -> u8 {
+        // FX0A is reached mid-frame, three instructions in
+        let program = [0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0xF1, 0x0A, 0x12, 0x08];
+        let mut machine = Chip8::from_rom(&program, Mode::Chip8).unwrap();
+        let mut cycle = 0;
+        for frame in frames {
+            let mut input = frame.clone();
+            for _ in 0..CYCLES_PER_FRAME {
+                on_cycle(cycle, &mut input).unwrap();
+                machine.step(&input).unwrap();
+                cycle += 1;
+            }
+        }
+
+        machine.registers[1]
+    }
+
+    #[test]
+    fn a_press_latched_for_a_frame_replays_the_same() {
+        let mut pressed = KeyboardState::new();
+        pressed.keys_pressed[5] = true;
+        pressed.pressed_key = Some(5);
+        let frames = [pressed, KeyboardState::new()];
+
+        let path = std::env::temp_dir().join(format!("chip8-replay-{}.txt", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut recorder = Recorder::create(path).unwrap();
+        let recorded = run(&frames, |cycle, input| recorder.record(cycle, input));
+        recorder.flush().unwrap();
+        assert_eq!(recorded, 5);
+
+        let mut player = Player::open(path).unwrap();
+        let idle = [KeyboardState::new(), KeyboardState::new()];
+        let replayed = run(&idle, |cycle, input| {
+            player.apply(cycle, input);
+            Ok(())
+        });
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(replayed, recorded);
+    }
+}
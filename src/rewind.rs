@@ -0,0 +1,58 @@
+//! Stepping backwards through recent frames. A snapshot of the whole machine is kept per
+//! emulated frame, up to --rewind-depth of them.
+
+use std::collections::VecDeque;
+
+use chip8::Chip8;
+
+pub struct Rewind {
+    snapshots: VecDeque<Chip8>,
+    depth: usize,
+}
+
+impl Rewind {
+    pub fn new(depth: usize) -> Self {
+        Self {
+            snapshots: VecDeque::with_capacity(depth),
+            depth,
+        }
+    }
+
+    /// Keeps a snapshot, dropping the oldest once there are `depth` of them.
+    pub fn record(&mut self, machine: &Chip8) {
+        if self.depth == 0 {
+            return;
+        }
+
+        if self.snapshots.len() == self.depth {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back(machine.clone());
+    }
+
+    /// The most recent snapshot, removed so rewinding again steps further back.
+    pub fn rewind(&mut self) -> Option<Chip8> {
+        self.snapshots.pop_back()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chip8::Mode;
+
+    use super::*;
+
+    #[test]
+    fn rewinds_newest_first_and_forgets_beyond_depth() {
+        let mut rewind = Rewind::new(2);
+        let mut machine = Chip8::new(Mode::Chip8);
+        for value in 1..=3 {
+            machine.registers[0] = value;
+            rewind.record(&machine);
+        }
+
+        assert_eq!(rewind.rewind().map(|machine| machine.registers[0]), Some(3));
+        assert_eq!(rewind.rewind().map(|machine| machine.registers[0]), Some(2));
+        assert!(rewind.rewind().is_none());
+    }
+}
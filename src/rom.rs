@@ -0,0 +1,179 @@
+use std::{
+    fs,
+    io::{Error, ErrorKind},
+};
+
+/// Reads a program from a file or, with the `http` feature, an http(s) URL. With the `zip`
+/// feature, a `.zip` is opened and `entry` read from it, which can be left out when the
+/// archive only holds one file.
+///
+/// Downloads stop after `limit` bytes so a large file can't be pulled into memory
+/// only to be rejected by `Chip8::load`.
+pub fn read_program(location: &str, entry: Option<&str>, limit: usize) -> Result<Vec<u8>, Error> {
+    let data = if location.starts_with("http://") || location.starts_with("https://") {
+        download(location, limit)?
+    } else {
+        fs::read(location)?
+    };
+
+    if !location.to_ascii_lowercase().ends_with(".zip") {
+        return Ok(data);
+    }
+
+    extract(&data, entry, limit).map_err(|e| Error::new(e.kind(), format!("{}: {}", location, e)))
+}
+
+/// Parses pasted hex into a program, e.g. `00E0 A22A 600C`. Separators are whitespace or
+/// commas and each group can have a `0x` prefix.
+pub fn parse_hex(text: &str) -> Result<Vec<u8>, String> {
+    let mut program = Vec::new();
+    for group in text.split(|c: char| c.is_whitespace() || c == ',') {
+        let digits = group.strip_prefix("0x").unwrap_or(group);
+        if digits.len() % 2 == 1 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("'{}' isn't whole hex bytes", group));
+        }
+
+        for i in (0..digits.len()).step_by(2) {
+            program.push(u8::from_str_radix(&digits[i..i + 2], 16).unwrap());
+        }
+    }
+
+    if program.is_empty() {
+        return Err("there's no hex to load".to_string());
+    }
+
+    Ok(program)
+}
+
+#[cfg(feature = "zip")]
+fn extract(archive: &[u8], entry: Option<&str>, limit: usize) -> Result<Vec<u8>, Error> {
+    use std::io::{Cursor, Read};
+
+    let mut archive = zip::ZipArchive::new(Cursor::new(archive)).map_err(Error::other)?;
+    let name = match entry {
+        Some(name) => name.to_string(),
+        None => {
+            let files: Vec<String> = archive
+                .file_names()
+                .filter_map(Result::ok)
+                .filter(|name| !name.ends_with('/'))
+                .map(String::from)
+                .collect();
+            match &files[..] {
+                [name] => name.clone(),
+                _ => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("pick one of {} with --entry", files.join(", ")),
+                    ))
+                }
+            }
+        }
+    };
+
+    let file = archive.by_name(&name).map_err(|e| match e {
+        zip::result::ZipError::FileNotFound => {
+            Error::new(ErrorKind::NotFound, format!("no entry named {}", name))
+        }
+        e => Error::other(e),
+    })?;
+    let mut program = Vec::new();
+    file.take(limit as u64 + 1).read_to_end(&mut program)?;
+    if program.len() > limit {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("{} is larger than the {} bytes available", name, limit),
+        ));
+    }
+
+    Ok(program)
+}
+
+#[cfg(not(feature = "zip"))]
+fn extract(_archive: &[u8], _entry: Option<&str>, _limit: usize) -> Result<Vec<u8>, Error> {
+    Err(Error::new(
+        ErrorKind::Unsupported,
+        "can't open archives, built without the zip feature",
+    ))
+}
+
+#[cfg(feature = "http")]
+fn download(url: &str, limit: usize) -> Result<Vec<u8>, Error> {
+    use std::io::Read;
+
+    let mut response = ureq::get(url).call().map_err(Error::other)?;
+    let mut program = Vec::new();
+    response
+        .body_mut()
+        .as_reader()
+        .take(limit as u64 + 1)
+        .read_to_end(&mut program)?;
+
+    if program.len() > limit {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("{} is larger than the {} bytes available", url, limit),
+        ));
+    }
+
+    Ok(program)
+}
+
+#[cfg(not(feature = "http"))]
+fn download(url: &str, _limit: usize) -> Result<Vec<u8>, Error> {
+    Err(Error::new(
+        ErrorKind::Unsupported,
+        format!("can't load {}, built without the http feature", url),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_pasted_hex_groups() {
+        assert_eq!(
+            parse_hex("00E0 0xA22A,\n12 00").unwrap(),
+            [0x00, 0xE0, 0xA2, 0x2A, 0x12, 0x00]
+        );
+        assert!(parse_hex("00E").is_err());
+        assert!(parse_hex("hello").is_err());
+        assert!(parse_hex(" \n").is_err());
+    }
+
+    #[cfg(feature = "zip")]
+    fn archive(files: &[(&str, &[u8])]) -> Vec<u8> {
+        use std::io::{Cursor, Write};
+
+        use zip::write::{SimpleFileOptions, ZipWriter};
+
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, data) in files {
+            writer
+                .start_file(*name, SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(data).unwrap();
+        }
+
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[cfg(feature = "zip")]
+    #[test]
+    fn extracts_the_only_entry_or_the_named_one() {
+        let single = archive(&[("pong.ch8", &[0x12, 0x00])]);
+        assert_eq!(extract(&single, None, 4096).unwrap(), [0x12, 0x00]);
+
+        let pack = archive(&[("a.ch8", &[1]), ("b.ch8", &[2])]);
+        assert_eq!(extract(&pack, Some("b.ch8"), 4096).unwrap(), [2]);
+        assert_eq!(
+            extract(&pack, None, 4096).unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+        assert_eq!(
+            extract(&pack, Some("c.ch8"), 4096).unwrap_err().kind(),
+            ErrorKind::NotFound
+        );
+    }
+}
@@ -0,0 +1,190 @@
+//! Recommended settings for known programs, looked up by the SHA-1 of the ROM.
+//!
+//! The table uses the `programs.json` format from the community CHIP-8 database
+//! (<https://github.com/chip-8/chip-8-database>), so it can be refreshed by
+//! replacing `romdb.json` with that file or passing it with `--rom-db`.
+//!
+//! The embedded `romdb.json` only lists the IBM logo ROM bundled in `tests/roms` so far. Until
+//! the community data is vendored with its license, games are only matched with `--rom-db`.
+
+use std::{collections::HashMap, fs, io::Error};
+
+use chip8::{Mode, Quirks};
+use serde::Deserialize;
+use sha1::{Digest, Sha1};
+
+const EMBEDDED: &str = include_str!("romdb.json");
+
+#[derive(Deserialize)]
+struct Program {
+    title: String,
+    #[serde(default)]
+    roms: HashMap<String, Rom>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Rom {
+    #[serde(default)]
+    platforms: Vec<String>,
+    #[serde(default)]
+    quirky_platforms: HashMap<String, QuirkOverrides>,
+}
+
+/// Quirks a ROM needs that differ from its platform's usual behavior.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct QuirkOverrides {
+    shift: Option<bool>,
+    memory_leave_i_unchanged: Option<bool>,
+    jump: Option<bool>,
+    vblank: Option<bool>,
+    logic: Option<bool>,
+}
+
+/// The settings matched for a ROM.
+pub struct RomProfile {
+    pub title: String,
+    pub platform: String,
+    pub mode: Mode,
+    pub quirks: Quirks,
+}
+
+pub struct RomDatabase {
+    programs: Vec<Program>,
+}
+
+impl RomDatabase {
+    /// The database built into the binary.
+    pub fn embedded() -> Result<Self, Error> {
+        Self::parse(EMBEDDED)
+    }
+
+    pub fn open(path: &str) -> Result<Self, Error> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    fn parse(json: &str) -> Result<Self, Error> {
+        Ok(Self {
+            programs: serde_json::from_str(json)?,
+        })
+    }
+
+    /// Finds the profile for `rom`, using the first platform listed for it that can be emulated.
+    pub fn lookup(&self, rom: &[u8]) -> Option<RomProfile> {
+        let hash = rom_hash(rom);
+        self.programs.iter().find_map(|program| {
+            let rom = program.roms.get(&hash)?;
+            rom.platforms.iter().find_map(|platform| {
+                let (mode, mut quirks) = platform_settings(platform)?;
+                if let Some(overrides) = rom.quirky_platforms.get(platform) {
+                    overrides.apply(&mut quirks);
+                }
+
+                Some(RomProfile {
+                    title: program.title.clone(),
+                    platform: platform.clone(),
+                    mode,
+                    quirks,
+                })
+            })
+        })
+    }
+}
+
+/// The SHA-1 of `rom` in lowercase hex, as the database keys ROMs.
+pub fn rom_hash(rom: &[u8]) -> String {
+    format!("{:x}", Sha1::digest(rom))
+}
+
+impl QuirkOverrides {
+    fn apply(&self, quirks: &mut Quirks) {
+        // The database describes shifting in place, we describe shifting from Vy
+        if let Some(shift) = self.shift {
+            quirks.shift_vy = !shift;
+        }
+
+        if let Some(unchanged) = self.memory_leave_i_unchanged {
+            quirks.memory_increment = !unchanged;
+        }
+
+        if let Some(jump) = self.jump {
+            quirks.jump_vx = jump;
+        }
+
+        if let Some(vblank) = self.vblank {
+            quirks.display_wait = vblank;
+        }
+
+        if let Some(logic) = self.logic {
+            quirks.vf_reset = logic;
+        }
+    }
+}
+
+/// Maps a database platform id onto the closest mode and quirks we support.
+fn platform_settings(platform: &str) -> Option<(Mode, Quirks)> {
+    match platform {
+        "originalChip8" | "hybridVIP" => Some((Mode::Chip8, Quirks::from(&Mode::Chip8))),
+        "modernChip8" => Some((
+            Mode::Chip8,
+            Quirks {
+                vf_reset: false,
+                memory_increment: false,
+                shift_vy: false,
+                jump_vx: false,
+                display_wait: false,
+                draw_cost: false,
+                collision_rows: false,
+            },
+        )),
+        "chip48" | "superchip1" | "superchip" => Some((Mode::Chip48, Quirks::from(&Mode::Chip48))),
+        "xochip" => Some((Mode::XoChip, Quirks::from(&Mode::XoChip))),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lookup_matches_hash_and_applies_quirk_overrides() {
+        let database = RomDatabase::parse(
+            r#"[{
+                "title": "Loop",
+                "roms": {
+                    "92a5652d382a18e89c4881ec57041fc7d885ca80": {
+                        "platforms": ["megachip8", "originalChip8"],
+                        "quirkyPlatforms": { "originalChip8": { "vblank": false } }
+                    }
+                }
+            }]"#,
+        )
+        .unwrap();
+
+        let profile = database.lookup(&[0x12, 0x00]).unwrap();
+        assert_eq!(profile.title, "Loop");
+        assert_eq!(profile.platform, "originalChip8");
+        assert!(matches!(profile.mode, Mode::Chip8));
+        assert!(!profile.quirks.display_wait);
+        assert!(profile.quirks.vf_reset);
+
+        assert!(database.lookup(&[0x12, 0x02]).is_none());
+    }
+
+    #[test]
+    fn embedded_database_knows_the_ibm_logo() {
+        let rom = include_bytes!("../tests/roms/ibm.ch8");
+        let profile = RomDatabase::embedded().unwrap().lookup(rom).unwrap();
+        assert_eq!(profile.title, "IBM Logo");
+        assert!(matches!(profile.mode, Mode::Chip8));
+    }
+
+    #[test]
+    fn xochip_roms_run_in_xochip_mode() {
+        let (mode, quirks) = platform_settings("xochip").unwrap();
+        assert!(matches!(mode, Mode::XoChip));
+        assert_eq!(quirks, Quirks::from(&Mode::XoChip));
+    }
+}
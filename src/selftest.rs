@@ -0,0 +1,96 @@
+//! `--selftest`: tiny programs that check each quirk behaves as configured, so a mode and
+//! quirk settings can be confirmed without hunting down test ROMs.
+
+use std::io::{Error, Write};
+
+use chip8::{asm, Chip8, KeyboardState, Quirks, PROGRAM_START_ADDRESS};
+
+struct Check {
+    quirk: &'static str,
+    source: &'static str,
+    /// Whether the machine behaved as if the quirk is on.
+    observed: fn(&Chip8) -> bool,
+    configured: fn(&Quirks) -> bool,
+}
+
+const CHECKS: [Check; 5] = [
+    Check {
+        quirk: "vf_reset",
+        source: "LD VF, 5\nOR V0, V1",
+        observed: |machine| machine.registers[0xF] == 0,
+        configured: |quirks| quirks.vf_reset,
+    },
+    Check {
+        quirk: "memory_increment",
+        source: "LD I, 0x300\nLD [I], V1",
+        observed: |machine| machine.index_register == 0x302,
+        configured: |quirks| quirks.memory_increment,
+    },
+    Check {
+        quirk: "shift_vy",
+        source: "LD V1, 4\nSHR V0, V1",
+        observed: |machine| machine.registers[0] == 2,
+        configured: |quirks| quirks.shift_vy,
+    },
+    Check {
+        quirk: "jump_vx",
+        // B208 reads its offset from V2 when jumping by Vx
+        source: "LD V2, 2\nJP V0, 0x208",
+        observed: |machine| machine.program_counter == 0x20A,
+        configured: |quirks| quirks.jump_vx,
+    },
+    Check {
+        quirk: "display_wait",
+        source: "LD I, 0x50\nDRW V0, V0, 1\nDRW V0, V0, 1",
+        observed: |machine| machine.is_waiting_for_vblank(),
+        configured: |quirks| quirks.display_wait,
+    },
+];
+
+/// Runs every check on a copy of `chip8`, returning whether they all passed.
+pub fn run(chip8: &Chip8, mut output: impl Write) -> Result<bool, Error> {
+    writeln!(output, "Mode: {:?}", chip8.mode)?;
+    let mut passed = true;
+    for check in &CHECKS {
+        let program = asm::assemble(check.source).map_err(Error::other)?;
+        let mut machine = chip8.clone();
+        machine.reset();
+        machine.set_load_address(PROGRAM_START_ADDRESS)?;
+        machine.load(&program)?;
+        machine.run_cycles(program.len() / 2, &KeyboardState::new())?;
+
+        let expected = (check.configured)(&machine.quirks);
+        let pass = (check.observed)(&machine) == expected;
+        passed &= pass;
+        writeln!(
+            output,
+            "{} {:<18} {}",
+            if pass { "PASS" } else { "FAIL" },
+            check.quirk,
+            if expected { "on" } else { "off" }
+        )?;
+    }
+
+    Ok(passed)
+}
+
+#[cfg(test)]
+mod tests {
+    use chip8::Mode;
+
+    use super::*;
+
+    #[test]
+    fn every_mode_passes_with_quirks_on_and_off() {
+        for mode in [Mode::Chip8, Mode::Chip48, Mode::XoChip] {
+            let mut chip8 = Chip8::new(mode);
+            assert!(run(&chip8, Vec::new()).unwrap());
+
+            chip8.quirks.display_wait = !chip8.quirks.display_wait;
+            chip8.quirks.jump_vx = !chip8.quirks.jump_vx;
+            let mut output = Vec::new();
+            assert!(run(&chip8, &mut output).unwrap());
+            assert_eq!(String::from_utf8(output).unwrap().lines().count(), 6);
+        }
+    }
+}
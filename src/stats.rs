@@ -0,0 +1,33 @@
+use std::time::{Duration, Instant};
+
+/// Measures how often something happens, averaged over one second windows.
+pub struct RateCounter {
+    count: u32,
+    window_start: Instant,
+    rate: f64,
+}
+
+impl RateCounter {
+    pub fn new() -> Self {
+        Self {
+            count: 0,
+            window_start: Instant::now(),
+            rate: 0.0,
+        }
+    }
+
+    pub fn tick(&mut self) {
+        self.count += 1;
+        let elapsed = self.window_start.elapsed();
+        if elapsed >= Duration::from_secs(1) {
+            self.rate = self.count as f64 / elapsed.as_secs_f64();
+            self.count = 0;
+            self.window_start = Instant::now();
+        }
+    }
+
+    /// Events per second over the last complete window.
+    pub fn rate(&self) -> f64 {
+        self.rate
+    }
+}
@@ -0,0 +1,81 @@
+use std::{
+    io::{stdout, Error},
+    panic,
+};
+
+use crossterm::{
+    cursor,
+    event::{
+        self, KeyboardEnhancementFlags, PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+    },
+    execute,
+    style::ResetColor,
+    terminal,
+};
+
+/// Puts the terminal into raw mode for the emulator and restores it when dropped.
+pub struct TerminalGuard {
+    key_releases: bool,
+}
+
+impl TerminalGuard {
+    pub fn new() -> Result<Self, Error> {
+        // Restore the terminal before the panic message is printed, otherwise
+        // it ends up garbled by raw mode.
+        let default_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            restore();
+            default_hook(info);
+        }));
+
+        terminal::enable_raw_mode()?;
+        let guard = Self {
+            key_releases: terminal::supports_keyboard_enhancement().unwrap_or(false),
+        };
+
+        if guard.key_releases {
+            execute!(
+                stdout(),
+                PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES)
+            )?;
+        }
+
+        execute!(
+            stdout(),
+            event::EnableFocusChange,
+            cursor::Hide,
+            terminal::Clear(terminal::ClearType::All)
+        )?;
+        // Only for pasting programs, so a terminal without it shouldn't stop the emulator
+        let _ = execute!(stdout(), event::EnableBracketedPaste);
+
+        Ok(guard)
+    }
+
+    /// Whether the terminal reports key releases, through the kitty keyboard protocol.
+    pub fn reports_key_releases(&self) -> bool {
+        self.key_releases
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        restore();
+    }
+}
+
+/// Undo everything `TerminalGuard::new` did, ignoring errors since this runs
+/// while tearing down. Terminals without keyboard enhancements ignore the pop.
+fn restore() {
+    let _ = execute!(
+        stdout(),
+        PopKeyboardEnhancementFlags,
+        event::DisableFocusChange,
+        event::DisableBracketedPaste,
+        ResetColor,
+        terminal::Clear(terminal::ClearType::All),
+        cursor::MoveTo(0, 0),
+        cursor::Show
+    );
+    let _ = terminal::disable_raw_mode();
+}
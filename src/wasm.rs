@@ -0,0 +1,115 @@
+//! JavaScript bindings for running the emulator in a browser.
+//!
+//! Build the module with:
+//!
+//! ```text
+//! cargo rustc --lib --release --target wasm32-unknown-unknown \
+//!     --no-default-features --features wasm --crate-type cdylib
+//! ```
+//!
+//! and generate the JS glue with `wasm-bindgen`.
+
+use wasm_bindgen::prelude::*;
+
+use crate::{Actions, Chip8, KeyboardState, Mode};
+
+#[wasm_bindgen]
+pub struct Emulator {
+    machine: Chip8,
+    keyboard: KeyboardState,
+}
+
+#[wasm_bindgen]
+impl Emulator {
+    #[wasm_bindgen(constructor)]
+    pub fn new(mode: Mode) -> Self {
+        Self {
+            machine: Chip8::new(mode),
+            keyboard: KeyboardState::new(),
+        }
+    }
+
+    pub fn load(&mut self, program: &[u8]) -> Result<(), JsError> {
+        self.machine
+            .load(program)
+            .map_err(|e| JsError::new(&e.to_string()))
+    }
+
+    /// Runs one instruction, returning true when the display should be redrawn.
+    pub fn step(&mut self) -> Result<bool, JsError> {
+        let action = self
+            .machine
+            .step(&self.keyboard)
+            .map_err(|e| JsError::new(&e.to_string()))?;
+
+        // A press is only reported for a single instruction, like the terminal frontend.
+        self.keyboard.pressed_key = None;
+
+        Ok(matches!(action, Actions::Redraw))
+    }
+
+    /// Runs up to `n` instructions, a frame's worth in one call, returning true when the
+    /// display should be redrawn.
+    pub fn run_cycles(&mut self, n: usize) -> Result<bool, JsError> {
+        let redraw = self
+            .machine
+            .run_cycles(n, &self.keyboard)
+            .map_err(|e| JsError::new(&e.to_string()))?;
+        self.keyboard.pressed_key = None;
+
+        Ok(redraw)
+    }
+
+    /// Instructions run so far, for syncing audio or video capture to emulated time.
+    pub fn cycles(&self) -> u64 {
+        self.machine.cycles()
+    }
+
+    /// Seconds of emulated time so far when running `speed` instructions per second.
+    pub fn elapsed_seconds(&self, speed: u64) -> f64 {
+        self.machine.elapsed(speed).as_secs_f64()
+    }
+
+    pub fn is_beeping(&self) -> bool {
+        self.machine.is_beeping()
+    }
+
+    /// The XO-CHIP sample to play while beeping, 128 bits from the high bit of the first byte.
+    pub fn audio_pattern(&self) -> Vec<u8> {
+        self.machine.audio_pattern().to_vec()
+    }
+
+    /// The XO-CHIP pitch, which plays the pattern at `4000 * 2^((pitch - 64) / 48)` bits per
+    /// second.
+    pub fn pitch(&self) -> u8 {
+        self.machine.pitch()
+    }
+
+    /// Counts the timers down, to be called at 60hz. Returns whether the beeper should be on.
+    pub fn tick_timers(&mut self) -> bool {
+        self.machine.tick_timers()
+    }
+
+    pub fn key_down(&mut self, key: u8) {
+        if let Some(pressed) = self.keyboard.keys_pressed.get_mut(key as usize) {
+            *pressed = true;
+            self.keyboard.pressed_key = Some(key);
+        }
+    }
+
+    pub fn key_up(&mut self, key: u8) {
+        if let Some(pressed) = self.keyboard.keys_pressed.get_mut(key as usize) {
+            *pressed = false;
+        }
+    }
+
+    /// The display as 32 rows of 64 bits, the leftmost pixel in the highest bit.
+    pub fn display_bits(&self) -> Vec<u64> {
+        self.machine.display_bits().to_vec()
+    }
+
+    /// The display as 64x32 bytes, row by row, one byte per pixel.
+    pub fn display(&self) -> Vec<u8> {
+        self.machine.display.iter().flatten().copied().collect()
+    }
+}
@@ -0,0 +1,92 @@
+//! Reloading programs when their files change, for --watch-rom.
+
+use std::{
+    io::Error,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver},
+    time::{Duration, Instant},
+};
+
+use notify::{recommended_watcher, Event, RecommendedWatcher, RecursiveMode, Watcher};
+
+/// How long a file has to stay unchanged before it's reloaded, since a single save can be
+/// several writes.
+const SETTLE: Duration = Duration::from_millis(200);
+
+pub struct RomWatcher {
+    /// Kept alive for as long as events are wanted.
+    _watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<Event>>,
+    paths: Vec<PathBuf>,
+    /// When each changed program was last written, until it settles.
+    changed: Vec<Option<Instant>>,
+}
+
+impl RomWatcher {
+    /// Watches the programs at `locations` that are local files. The directories are watched
+    /// rather than the files, so editors that save by replacing the file are still seen.
+    pub fn new(locations: &[String]) -> Result<Self, Error> {
+        let (sender, events) = mpsc::channel();
+        let mut watcher = recommended_watcher(sender).map_err(Error::other)?;
+        let paths: Vec<PathBuf> = locations
+            .iter()
+            .map(|location| Path::new(location).canonicalize().unwrap_or_default())
+            .collect();
+        for path in &paths {
+            if let Some(directory) = path.parent().filter(|_| path.is_file()) {
+                watcher
+                    .watch(directory, RecursiveMode::NonRecursive)
+                    .map_err(Error::other)?;
+            }
+        }
+
+        Ok(Self {
+            _watcher: watcher,
+            events,
+            changed: vec![None; paths.len()],
+            paths,
+        })
+    }
+
+    /// The index of a program that changed and has since settled, if any.
+    pub fn changed(&mut self) -> Option<usize> {
+        let now = Instant::now();
+        while let Ok(event) = self.events.try_recv() {
+            let Ok(event) = event else { continue };
+            if !(event.kind.is_create() || event.kind.is_modify()) {
+                continue;
+            }
+
+            for (path, changed) in self.paths.iter().zip(self.changed.iter_mut()) {
+                if event.paths.contains(path) {
+                    *changed = Some(now);
+                }
+            }
+        }
+
+        settled(&mut self.changed, now)
+    }
+}
+
+/// Takes the first change that's older than `SETTLE`.
+fn settled(changed: &mut [Option<Instant>], now: Instant) -> Option<usize> {
+    let index = changed
+        .iter()
+        .position(|written| written.is_some_and(|written| now - written >= SETTLE))?;
+    changed[index] = None;
+    Some(index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changes_are_reported_once_they_settle() {
+        let start = Instant::now();
+        let mut changed = [None, Some(start)];
+        assert_eq!(settled(&mut changed, start + SETTLE / 2), None);
+        assert_eq!(settled(&mut changed, start + SETTLE), Some(1));
+        assert_eq!(settled(&mut changed, start + SETTLE * 2), None);
+    }
+}
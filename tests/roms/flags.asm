@@ -0,0 +1,34 @@
+; Draws VF after each flag setting instruction as a digit, left to right:
+; 8XY4 carry, 8XY4 no carry, 8XY5 no borrow, 8XY5 borrow, 8XY7 no borrow,
+; 8XY6 shifting out a 1, 8XYE shifting out a 0. Expect 1010110.
+        LD V1, 0
+        LD V2, 0
+        LD V4, 1
+
+        LD V3, 0xFF
+        ADD V3, V4
+        CALL show
+        LD V3, 1
+        ADD V3, V4
+        CALL show
+        LD V3, 5
+        SUB V3, V4
+        CALL show
+        LD V3, 0
+        SUB V3, V4
+        CALL show
+        LD V3, 0
+        SUBN V3, V4
+        CALL show
+        LD V3, 3
+        SHR V3, V3
+        CALL show
+        LD V3, 0x40
+        SHL V3, V3
+        CALL show
+done:   JP done
+
+show:   LD F, VF
+        DRW V1, V2, 5
+        ADD V1, 5
+        RET
@@ -0,0 +1,120 @@
+//! Runs each program in `tests/roms` and compares the display against the bitmap next to it.
+//!
+//! A program is either assembly (`name.asm`) or a ROM (`name.ch8`). The expected display,
+//! `name.txt`, starts with a `# mode: <mode>, frames: <n>` line followed by 32 rows of `#` for
+//! pixels that are on and `.` for those that are off. A `poke: <address>=<byte>` setting writes
+//! to memory before running, for ROMs that read a menu choice from memory instead of a key.
+//!
+//! Only ROMs that can be redistributed are bundled. To check the standard community test ROMs
+//! as well, set `CHIP8_TEST_ROMS` to a directory laid out the same way. Timendus' suite
+//! (<https://github.com/Timendus/chip8-test-suite>) has the corax89 opcode, flags and quirks
+//! tests with screenshots of their expected output; BC_test has to be found separately. Copy
+//! each `.ch8` in and transcribe its expected screen into the `.txt` next to it.
+
+use std::{fs, path::Path};
+
+use chip8::{asm, Chip8, KeyboardState, Mode};
+
+/// Instructions per 60hz frame, a --speed of 700 like the expected displays were made with.
+const CYCLES_PER_FRAME: usize = 11;
+
+fn render(display: &[[u8; 64]; 32]) -> String {
+    display
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|&pixel| if pixel == 0 { '.' } else { '#' })
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn run(program: &[u8], settings: &Settings) -> String {
+    let mut machine = Chip8::from_rom(program, settings.mode.clone()).unwrap();
+    for &(address, value) in &settings.pokes {
+        machine.memory[address] = value;
+    }
+
+    let keyboard = KeyboardState::new();
+    for _ in 0..settings.frames {
+        machine.run_cycles(CYCLES_PER_FRAME, &keyboard).unwrap();
+
+        machine.tick_timers();
+        machine.vblank();
+    }
+
+    render(&machine.display)
+}
+
+/// How to run a program, from the first line of its expected display.
+struct Settings {
+    mode: Mode,
+    frames: usize,
+    pokes: Vec<(usize, u8)>,
+}
+
+fn parse_header(header: &str) -> Settings {
+    let mut settings = Settings {
+        mode: Mode::Chip8,
+        frames: 60,
+        pokes: Vec::new(),
+    };
+    for setting in header.trim_start_matches('#').split(',') {
+        match setting.split_once(':').map(|(k, v)| (k.trim(), v.trim())) {
+            Some(("mode", "chip8")) => settings.mode = Mode::Chip8,
+            Some(("mode", "chip48")) => settings.mode = Mode::Chip48,
+            Some(("mode", "xochip")) => settings.mode = Mode::XoChip,
+            Some(("frames", n)) => settings.frames = n.parse().unwrap(),
+            Some(("poke", poke)) => {
+                let (address, value) = poke.split_once('=').unwrap();
+                let hex = |text: &str| usize::from_str_radix(text.trim_start_matches("0x"), 16);
+                settings
+                    .pokes
+                    .push((hex(address).unwrap(), hex(value).unwrap() as u8));
+            }
+            _ => panic!("unknown setting '{}'", setting),
+        }
+    }
+
+    settings
+}
+
+/// Checks every program in `directory`, returning how many there were.
+fn check_directory(directory: &Path) -> usize {
+    let mut checked = 0;
+    for entry in fs::read_dir(directory).unwrap() {
+        let path = entry.unwrap().path();
+        let program = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("asm") => asm::assemble(&fs::read_to_string(&path).unwrap()).unwrap(),
+            Some("ch8") => fs::read(&path).unwrap(),
+            _ => continue,
+        };
+
+        let expected = fs::read_to_string(path.with_extension("txt")).unwrap();
+        let (header, bitmap) = expected.split_once('\n').unwrap();
+        assert_eq!(
+            run(&program, &parse_header(header)),
+            bitmap.trim_end(),
+            "{}",
+            path.display()
+        );
+        checked += 1;
+    }
+
+    checked
+}
+
+#[test]
+fn test_roms_draw_their_expected_displays() {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/roms");
+    assert!(check_directory(&directory) > 0);
+}
+
+#[test]
+fn standard_test_roms_draw_their_expected_displays() {
+    // Skipped unless pointed at a copy of the standard ROMs
+    if let Some(directory) = std::env::var_os("CHIP8_TEST_ROMS") {
+        assert!(check_directory(Path::new(&directory)) > 0);
+    }
+}